@@ -0,0 +1,55 @@
+/// What the player asks the game to do on a frame, whatever device it came from
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GameAction {
+    MoveLeft,
+    MoveRight,
+    RotateClockwise,
+    RotateCounterClockwise,
+    Rotate180,
+    SoftDrop,
+    HardDrop,
+    Hold,
+    Pause,
+}
+
+impl GameAction {
+    pub const ALL: [GameAction; 9] = [
+        GameAction::MoveLeft,
+        GameAction::MoveRight,
+        GameAction::RotateClockwise,
+        GameAction::RotateCounterClockwise,
+        GameAction::Rotate180,
+        GameAction::SoftDrop,
+        GameAction::HardDrop,
+        GameAction::Hold,
+        GameAction::Pause,
+    ];
+
+    /// Name of the action in the [controls] section of the config file
+    pub fn name(self) -> &'static str {
+        match self {
+            GameAction::MoveLeft => "move_left",
+            GameAction::MoveRight => "move_right",
+            GameAction::RotateClockwise => "rotate_cw",
+            GameAction::RotateCounterClockwise => "rotate_ccw",
+            GameAction::Rotate180 => "rotate_180",
+            GameAction::SoftDrop => "soft_drop",
+            GameAction::HardDrop => "hard_drop",
+            GameAction::Hold => "hold",
+            GameAction::Pause => "pause",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|action| action.name() == name)
+    }
+
+    /// Number identifying the action in replay files
+    pub fn id(self) -> u8 {
+        Self::ALL.iter().position(|&action| action == self).unwrap_or(0) as u8
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        Self::ALL.get(id as usize).copied()
+    }
+}
@@ -1,71 +1,665 @@
-use std::{fs::File, io::{BufReader, Error, ErrorKind, Read, Seek, SeekFrom}, usize};
+use std::{fmt, io};
 
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{ByteOrder, LittleEndian};
+
+const FILE_HEADER_SIZE: usize = 14;
+const CORE_HEADER_SIZE: u32 = 12;
+const INFO_HEADER_SIZE: u32 = 40;
+const V2_HEADER_SIZE: u32 = 52;
+const V3_HEADER_SIZE: u32 = 56;
+const V4_HEADER_SIZE: u32 = 108;
+const V5_HEADER_SIZE: u32 = 124;
+
+const BI_RGB: u32 = 0;
+const BI_RLE8: u32 = 1;
+const BI_RLE4: u32 = 2;
+const BI_BITFIELDS: u32 = 3;
+const BI_ALPHABITFIELDS: u32 = 6;
+
+// Anything bigger is more likely a corrupt header than a skin
+const MAX_DIMENSION: u32 = 16384;
+
+/// Decoded image, pixels are ARGB from the top left corner, row by row
+pub struct Bitmap {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u32>,
+}
+
+#[derive(Debug)]
+pub enum BitmapError {
+    Io(io::Error),
+    InvalidSignature(u16),
+    UnsupportedHeader(u32),
+    UnsupportedBitCount(u16),
+    UnsupportedCompression(u32),
+    InvalidDimensions(i64, i64),
+    // A palette index past the end of the palette
+    InvalidColorIndex(u8),
+    // A channel mask with gaps in it or wider than a pixel
+    InvalidMask(u32),
+    // The file ends before the data its headers describe
+    Truncated,
+    #[cfg(feature = "png")]
+    Png(png::DecodingError),
+    // A PNG file in a build without the png feature
+    #[cfg(not(feature = "png"))]
+    PngNotSupported,
+}
+
+impl fmt::Display for BitmapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BitmapError::Io(error) => write!(f, "{}", error),
+            BitmapError::InvalidSignature(signature) => write!(f, "signature {:#06x} is not a bitmap signature", signature),
+            BitmapError::UnsupportedHeader(size) => write!(f, "unsupported bitmap header of {} bytes", size),
+            BitmapError::UnsupportedBitCount(bit_count) => write!(f, "unsupported bit count {}", bit_count),
+            BitmapError::UnsupportedCompression(compression) => write!(f, "unsupported compression {}", compression),
+            BitmapError::InvalidDimensions(width, height) => write!(f, "invalid dimensions {}x{}", width, height),
+            BitmapError::InvalidColorIndex(index) => write!(f, "color index {} is outside of the palette", index),
+            BitmapError::InvalidMask(mask) => write!(f, "invalid channel mask {:#010x}", mask),
+            BitmapError::Truncated => write!(f, "bitmap data is truncated"),
+            #[cfg(feature = "png")]
+            BitmapError::Png(error) => write!(f, "{}", error),
+            #[cfg(not(feature = "png"))]
+            BitmapError::PngNotSupported => write!(f, "PNG support is not enabled, build with the png feature"),
+        }
+    }
+}
+
+impl std::error::Error for BitmapError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BitmapError::Io(error) => Some(error),
+            #[cfg(feature = "png")]
+            BitmapError::Png(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for BitmapError {
+    fn from(error: io::Error) -> Self {
+        BitmapError::Io(error)
+    }
+}
+
+#[cfg(feature = "png")]
+impl From<png::DecodingError> for BitmapError {
+    fn from(error: png::DecodingError) -> Self {
+        BitmapError::Png(error)
+    }
+}
 
 #[derive(Debug)]
 struct BmpHeader {
-    offset: u32,
+    offset: usize,
+    header_size: u32,
     width: u32,
     height: u32,
-    bit_count: u16
+    // Rows are stored from the bottom up unless the height in the file is negative
+    top_down: bool,
+    bit_count: u16,
+    compression: u32,
+    colors_used: u32,
+    masks: Option<Masks>,
 }
 
-pub fn load_bitmap(filename: &str) -> Result<Vec<u32>, Error> {
-    let file = File::open(filename)?;
-    let mut buf_reader = BufReader::new(file);
+/// Where each channel sits in a 16 or 32 bits pixel
+#[derive(Debug, Copy, Clone)]
+struct Masks {
+    red: u32,
+    green: u32,
+    blue: u32,
+    alpha: u32,
+}
 
-    let header = match read_bitmap_header(&mut buf_reader) {
-        Ok(header) => header,
-        Err(error) => return Err(error)
+/// Decodes 1, 4 and 8 bits paletted bitmaps, RLE compressed or not, and 16, 24 and 32 bits bitmaps,
+/// with the core, info and V2 to V5 headers
+pub fn decode_bitmap(data: &[u8]) -> Result<Bitmap, BitmapError> {
+    let header = read_bitmap_header(data)?;
+    let palette = read_palette(data, &header)?;
+
+    let pixels = match (header.compression, header.bit_count) {
+        (BI_RLE8, 8) | (BI_RLE4, 4) => decode_rle(data, &header, &palette)?,
+        (BI_RGB, 1 | 4 | 8) => decode_paletted(data, &header, &palette)?,
+        (BI_RGB | BI_BITFIELDS | BI_ALPHABITFIELDS, 16 | 32) => decode_masked(data, &header)?,
+        (BI_RGB, 24) => decode_rgb(data, &header)?,
+        (BI_RGB | BI_RLE8 | BI_RLE4 | BI_BITFIELDS | BI_ALPHABITFIELDS, _) => return Err(BitmapError::UnsupportedBitCount(header.bit_count)),
+        (compression, _) => return Err(BitmapError::UnsupportedCompression(compression)),
     };
 
-    assert!(header.bit_count == 24, "Only 24 bits bitmaps are supported");
-    let bytes_per_pixel: u32 = (header.bit_count / 8) as u32;
-
-    let stride = (header.width * bytes_per_pixel + bytes_per_pixel) & (!bytes_per_pixel);
-    let size: usize = (header.height * stride) as usize;
-    let _ = buf_reader.seek(SeekFrom::Start(header.offset.into()));
-    let mut bitmap_buffer: Vec<u8> = vec![0; size];
-    buf_reader.read_exact(&mut bitmap_buffer)?;
-
-    let mut xrgb_array: Vec<u32> = vec![0; (header.width * header.height) as usize];
-    let mut xrgb_index = 0;
-    for y in (0..header.height).rev() {
-        for x in 0..header.width {
-            let index = (stride * y) + (x * bytes_per_pixel);
-            let r: u8 = bitmap_buffer[(index + 2) as usize];
-            let g: u8 = bitmap_buffer[(index + 1) as usize];
-            let b: u8 = bitmap_buffer[index as usize];
-
-            xrgb_array[xrgb_index] = (r as u32) << 16 | (g as u32) << 8 | (b as u32);
-            xrgb_index += 1;
-        }
+    Ok(Bitmap { width: header.width, height: header.height, pixels })
+}
+
+fn read_bitmap_header(data: &[u8]) -> Result<BmpHeader, BitmapError> {
+    let signature = read_u16(data, 0)?;
+    if signature != 0x4D42 {
+        return Err(BitmapError::InvalidSignature(signature));
     }
 
-    Ok(xrgb_array)
-}
+    let offset = read_u32(data, 10)? as usize;
+    let header_size = read_u32(data, FILE_HEADER_SIZE)?;
+    let info = FILE_HEADER_SIZE + 4;
 
-fn read_bitmap_header<T: Read + Seek>(reader: &mut T) -> Result<BmpHeader, Error> {
-    let bm_type = reader.read_u16::<LittleEndian>()?;
-    if bm_type != 0x4D42 {
-        return Err(Error::new(ErrorKind::Other, format!("bm_type({}) not valid for bitmap header", bm_type)));
+    if header_size == CORE_HEADER_SIZE {
+        let width = read_u16(data, info)? as i64;
+        let height = read_u16(data, info + 2)? as i64;
+        check_dimensions(width, height)?;
+
+        return Ok(BmpHeader {
+            offset,
+            header_size,
+            width: width as u32,
+            height: height as u32,
+            top_down: false,
+            bit_count: read_u16(data, info + 6)?,
+            compression: BI_RGB,
+            colors_used: 0,
+            masks: None,
+        });
     }
 
-    let _ = reader.seek(std::io::SeekFrom::Current(8));
-    let bm_offset = reader.read_u32::<LittleEndian>()?;
+    if !matches!(header_size, INFO_HEADER_SIZE | V2_HEADER_SIZE | V3_HEADER_SIZE | V4_HEADER_SIZE | V5_HEADER_SIZE) {
+        return Err(BitmapError::UnsupportedHeader(header_size));
+    }
 
-    // skip struct size
-    let _ = reader.seek(std::io::SeekFrom::Current(4));
-    let bm_width = reader.read_u32::<LittleEndian>()?;
-    let bm_height = reader.read_u32::<LittleEndian>()?;
-    // skip planes
-    let _ = reader.seek(std::io::SeekFrom::Current(2));
-    let bm_bit_count = reader.read_u16::<LittleEndian>()?;
+    let width = read_u32(data, info)? as i32 as i64;
+    let height = read_u32(data, info + 4)? as i32 as i64;
+    check_dimensions(width, height)?;
+    let bit_count = read_u16(data, info + 10)?;
+    let compression = read_u32(data, info + 12)?;
+    let colors_used = read_u32(data, info + 28)?;
+
+    // Masks follow the info header, inside the header itself from V2 on
+    let masks_offset = FILE_HEADER_SIZE + INFO_HEADER_SIZE as usize;
+    let masks = match compression {
+        BI_BITFIELDS | BI_ALPHABITFIELDS => {
+            let has_alpha = compression == BI_ALPHABITFIELDS || header_size >= V3_HEADER_SIZE;
+            Some(Masks {
+                red: read_u32(data, masks_offset)?,
+                green: read_u32(data, masks_offset + 4)?,
+                blue: read_u32(data, masks_offset + 8)?,
+                alpha: if has_alpha { read_u32(data, masks_offset + 12)? } else { 0 },
+            })
+        }
+        _ => None,
+    };
 
     Ok(BmpHeader {
-        offset: bm_offset,
-        width: bm_width,
-        height: bm_height,
-        bit_count: bm_bit_count
+        offset,
+        header_size,
+        width: width as u32,
+        height: height.unsigned_abs() as u32,
+        top_down: height < 0,
+        bit_count,
+        compression,
+        colors_used,
+        masks,
     })
 }
+
+fn check_dimensions(width: i64, height: i64) -> Result<(), BitmapError> {
+    if width <= 0 || height == 0 || width > MAX_DIMENSION as i64 || height.unsigned_abs() > MAX_DIMENSION as u64 {
+        return Err(BitmapError::InvalidDimensions(width, height));
+    }
+
+    Ok(())
+}
+
+fn read_palette(data: &[u8], header: &BmpHeader) -> Result<Vec<u32>, BitmapError> {
+    if header.bit_count > 8 {
+        return Ok(Vec::new());
+    }
+
+    let count = match header.colors_used {
+        0 => 1 << header.bit_count,
+        count => count.min(1 << header.bit_count),
+    } as usize;
+    // Core headers store colors as BGR, the others as BGR and an unused byte
+    let (start, entry_size) = if header.header_size == CORE_HEADER_SIZE {
+        (FILE_HEADER_SIZE + CORE_HEADER_SIZE as usize, 3)
+    } else {
+        let masks_size = match header.compression {
+            BI_BITFIELDS if header.header_size == INFO_HEADER_SIZE => 12,
+            BI_ALPHABITFIELDS if header.header_size == INFO_HEADER_SIZE => 16,
+            _ => 0,
+        };
+        (FILE_HEADER_SIZE + header.header_size as usize + masks_size, 4)
+    };
+
+    let bytes = data.get(start..start + count * entry_size).ok_or(BitmapError::Truncated)?;
+    Ok(bytes.chunks_exact(entry_size)
+        .map(|color| 0xFF000000 | (color[2] as u32) << 16 | (color[1] as u32) << 8 | color[0] as u32)
+        .collect())
+}
+
+// Rows are padded to 4 bytes
+fn stride(header: &BmpHeader) -> usize {
+    (header.width as usize * header.bit_count as usize).div_ceil(32) * 4
+}
+
+// Pixel rows as stored in the file, along with the row of the image each one is
+fn rows<'a>(data: &'a [u8], header: &BmpHeader) -> Result<impl Iterator<Item = (usize, &'a [u8])>, BitmapError> {
+    let stride = stride(header);
+    let height = header.height as usize;
+    let bytes = data.get(header.offset..header.offset + stride * height).ok_or(BitmapError::Truncated)?;
+    let top_down = header.top_down;
+
+    Ok(bytes.chunks_exact(stride).enumerate().map(move |(i, row)| (if top_down { i } else { height - 1 - i }, row)))
+}
+
+fn decode_paletted(data: &[u8], header: &BmpHeader, palette: &[u32]) -> Result<Vec<u32>, BitmapError> {
+    let width = header.width as usize;
+    let bits = header.bit_count as usize;
+    let rows = rows(data, header)?;
+    let mut pixels = vec![0; width * header.height as usize];
+
+    for (y, row) in rows {
+        for x in 0..width {
+            // Pixels are packed from the most significant bits of each byte
+            let bit = x * bits;
+            let index = (row[bit / 8] >> (8 - bits - bit % 8)) & ((1 << bits) - 1) as u8;
+            pixels[y * width + x] = palette_color(palette, index)?;
+        }
+    }
+
+    Ok(pixels)
+}
+
+fn decode_rgb(data: &[u8], header: &BmpHeader) -> Result<Vec<u32>, BitmapError> {
+    let width = header.width as usize;
+    let rows = rows(data, header)?;
+    let mut pixels = vec![0; width * header.height as usize];
+
+    for (y, row) in rows {
+        for (x, bgr) in row.chunks_exact(3).take(width).enumerate() {
+            pixels[y * width + x] = 0xFF000000 | (bgr[2] as u32) << 16 | (bgr[1] as u32) << 8 | bgr[0] as u32;
+        }
+    }
+
+    Ok(pixels)
+}
+
+fn decode_masked(data: &[u8], header: &BmpHeader) -> Result<Vec<u32>, BitmapError> {
+    let width = header.width as usize;
+    let bytes_per_pixel = header.bit_count as usize / 8;
+    let masks = header.masks.unwrap_or(match header.bit_count {
+        16 => Masks { red: 0x7C00, green: 0x03E0, blue: 0x001F, alpha: 0 },
+        _ => Masks { red: 0x00FF0000, green: 0x0000FF00, blue: 0x000000FF, alpha: 0xFF000000 },
+    });
+    for mask in [masks.red, masks.green, masks.blue, masks.alpha] {
+        check_mask(mask, header.bit_count)?;
+    }
+    let rows = rows(data, header)?;
+    let mut pixels = vec![0; width * header.height as usize];
+    let mut any_alpha = false;
+
+    for (y, row) in rows {
+        for (x, bytes) in row.chunks_exact(bytes_per_pixel).take(width).enumerate() {
+            let value = if bytes_per_pixel == 2 { LittleEndian::read_u16(bytes) as u32 } else { LittleEndian::read_u32(bytes) };
+            let alpha = channel(value, masks.alpha);
+            any_alpha |= alpha != 0;
+            pixels[y * width + x] = alpha << 24 | channel(value, masks.red) << 16 | channel(value, masks.green) << 8 | channel(value, masks.blue);
+        }
+    }
+
+    // Many writers leave the alpha byte of 32 bits bitmaps at zero, those are meant to be opaque
+    if !any_alpha {
+        pixels.iter_mut().for_each(|pixel| *pixel |= 0xFF000000);
+    }
+
+    Ok(pixels)
+}
+
+// Masks have to be a single run of bits inside the pixel, or zero for a missing channel
+fn check_mask(mask: u32, bit_count: u16) -> Result<(), BitmapError> {
+    if mask == 0 {
+        return Ok(());
+    }
+
+    let bits = (mask >> mask.trailing_zeros()) as u64;
+    if bits & (bits + 1) != 0 || (mask as u64) >> bit_count != 0 {
+        return Err(BitmapError::InvalidMask(mask));
+    }
+
+    Ok(())
+}
+
+// Extracts the channel under mask and scales it to 8 bits
+fn channel(value: u32, mask: u32) -> u32 {
+    if mask == 0 {
+        return 0;
+    }
+
+    // In 64 bits as a mask of up to 32 bits times 255 doesn't fit in 32
+    let max = (mask >> mask.trailing_zeros()) as u64;
+    ((((value & mask) >> mask.trailing_zeros()) as u64) * 255 / max) as u32
+}
+
+/// RLE8 and RLE4 runs, pixels skipped by a delta or an early end of line stay transparent
+fn decode_rle(data: &[u8], header: &BmpHeader, palette: &[u32]) -> Result<Vec<u32>, BitmapError> {
+    let width = header.width as usize;
+    let height = header.height as usize;
+    let four_bits = header.bit_count == 4;
+    let encoded = data.get(header.offset..).ok_or(BitmapError::Truncated)?;
+    // A run of two bytes gives at most 255 pixels, a file with less data than that for its size is cut short
+    // or only made of skips, either way it isn't worth allocating the pixels it claims to have
+    if width * height > encoded.len() / 2 * 255 {
+        return Err(BitmapError::Truncated);
+    }
+    let mut pixels = vec![0; width * height];
+    let mut bytes = encoded.iter().copied();
+    let mut next = || bytes.next().ok_or(BitmapError::Truncated);
+
+    // Position counted from the bottom row, as RLE bitmaps are always stored bottom up
+    let (mut x, mut row) = (0, 0);
+    let mut put = |x: &mut usize, row: usize, index: u8| -> Result<(), BitmapError> {
+        let color = palette_color(palette, index)?;
+        if *x < width && row < height {
+            let y = if header.top_down { row } else { height - 1 - row };
+            pixels[y * width + *x] = color;
+        }
+        *x += 1;
+        Ok(())
+    };
+
+    loop {
+        let count = next()? as usize;
+        let value = next()?;
+        if count > 0 {
+            for i in 0..count {
+                let index = if four_bits { if i % 2 == 0 { value >> 4 } else { value & 0x0F } } else { value };
+                put(&mut x, row, index)?;
+            }
+            continue;
+        }
+
+        match value {
+            0 => {
+                x = 0;
+                row += 1;
+            }
+            1 => break,
+            2 => {
+                x += next()? as usize;
+                row += next()? as usize;
+            }
+            count => {
+                let count = count as usize;
+                let byte_count = if four_bits { count.div_ceil(2) } else { count };
+                let mut absolute = Vec::with_capacity(byte_count);
+                for _ in 0..byte_count {
+                    absolute.push(next()?);
+                }
+                // Absolute runs are padded to 2 bytes
+                if byte_count % 2 == 1 {
+                    next()?;
+                }
+
+                for i in 0..count {
+                    let index = if four_bits {
+                        if i % 2 == 0 { absolute[i / 2] >> 4 } else { absolute[i / 2] & 0x0F }
+                    } else {
+                        absolute[i]
+                    };
+                    put(&mut x, row, index)?;
+                }
+            }
+        }
+    }
+
+    Ok(pixels)
+}
+
+fn palette_color(palette: &[u32], index: u8) -> Result<u32, BitmapError> {
+    palette.get(index as usize).copied().ok_or(BitmapError::InvalidColorIndex(index))
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, BitmapError> {
+    data.get(offset..offset + 2).map(LittleEndian::read_u16).ok_or(BitmapError::Truncated)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, BitmapError> {
+    data.get(offset..offset + 4).map(LittleEndian::read_u32).ok_or(BitmapError::Truncated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: u32 = 0xFFFF0000;
+    const GREEN: u32 = 0xFF00FF00;
+    const BLUE: u32 = 0xFF0000FF;
+    const WHITE: u32 = 0xFFFFFFFF;
+    const BLACK: u32 = 0xFF000000;
+
+    // File header, a header of header_size bytes with the masks at its end for V2 and up or right after it
+    // otherwise, the palette as BGR and an unused byte, and the pixel data
+    fn bitmap(header_size: u32, (width, height): (i32, i32), bit_count: u16, compression: u32, masks: &[u32], palette: &[u32], pixels: &[u8]) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend(header_size.to_le_bytes());
+        header.extend(width.to_le_bytes());
+        header.extend(height.to_le_bytes());
+        header.extend(1u16.to_le_bytes());
+        header.extend(bit_count.to_le_bytes());
+        header.extend(compression.to_le_bytes());
+        header.extend([0; 12]);
+        header.extend((palette.len() as u32).to_le_bytes());
+        header.extend([0; 4]);
+        masks.iter().for_each(|mask| header.extend(mask.to_le_bytes()));
+        header.resize(header.len().max(header_size as usize), 0);
+        palette.iter().for_each(|color| header.extend(color.to_le_bytes()));
+        with_file_header(header, pixels)
+    }
+
+    fn core_bitmap(width: u16, height: u16, bit_count: u16, palette: &[[u8; 3]], pixels: &[u8]) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend(CORE_HEADER_SIZE.to_le_bytes());
+        header.extend(width.to_le_bytes());
+        header.extend(height.to_le_bytes());
+        header.extend(1u16.to_le_bytes());
+        header.extend(bit_count.to_le_bytes());
+        palette.iter().for_each(|color| header.extend(color));
+        with_file_header(header, pixels)
+    }
+
+    fn with_file_header(header: Vec<u8>, pixels: &[u8]) -> Vec<u8> {
+        let offset = (FILE_HEADER_SIZE + header.len()) as u32;
+        let mut data = b"BM".to_vec();
+        data.extend((offset + pixels.len() as u32).to_le_bytes());
+        data.extend([0; 4]);
+        data.extend(offset.to_le_bytes());
+        data.extend(header);
+        data.extend(pixels);
+        data
+    }
+
+    fn decode(data: &[u8]) -> Bitmap {
+        match decode_bitmap(data) {
+            Ok(bitmap) => bitmap,
+            Err(error) => panic!("{}", error),
+        }
+    }
+
+    #[test]
+    fn paletted_1_bit() {
+        // Bottom row first, pixels from the most significant bit
+        let data = bitmap(INFO_HEADER_SIZE, (3, 2), 1, BI_RGB, &[], &[BLACK, WHITE], &[0b0100_0000, 0, 0, 0, 0b1010_0000, 0, 0, 0]);
+        let bitmap = decode(&data);
+        assert_eq!((bitmap.width, bitmap.height), (3, 2));
+        assert_eq!(bitmap.pixels, [WHITE, BLACK, WHITE, BLACK, WHITE, BLACK]);
+    }
+
+    #[test]
+    fn paletted_4_bit() {
+        let data = bitmap(INFO_HEADER_SIZE, (3, 1), 4, BI_RGB, &[], &[BLACK, RED, GREEN, BLUE], &[0x12, 0x30, 0, 0]);
+        assert_eq!(decode(&data).pixels, [RED, GREEN, BLUE]);
+    }
+
+    #[test]
+    fn paletted_8_bit_top_down() {
+        let data = bitmap(INFO_HEADER_SIZE, (2, -2), 8, BI_RGB, &[], &[RED, GREEN, BLUE], &[0, 1, 0, 0, 2, 0, 0, 0]);
+        let bitmap = decode(&data);
+        assert_eq!((bitmap.width, bitmap.height), (2, 2));
+        assert_eq!(bitmap.pixels, [RED, GREEN, BLUE, RED]);
+    }
+
+    #[test]
+    fn core_header_palette_is_bgr() {
+        let data = core_bitmap(2, 1, 1, &[[0xFF, 0, 0], [0, 0, 0xFF]], &[0b1000_0000, 0, 0, 0]);
+        assert_eq!(decode(&data).pixels, [RED, BLUE]);
+    }
+
+    #[test]
+    fn rgb_24_bit() {
+        // Rows of 6 bytes padded to 8
+        let data = bitmap(INFO_HEADER_SIZE, (2, 2), 24, BI_RGB, &[], &[], &[0, 0, 0xFF, 0, 0xFF, 0, 0, 0, 0xFF, 0, 0, 0xFF, 0xFF, 0xFF, 0, 0]);
+        assert_eq!(decode(&data).pixels, [BLUE, WHITE, RED, GREEN]);
+    }
+
+    #[test]
+    fn rgb_16_bit_defaults_to_555() {
+        let data = bitmap(INFO_HEADER_SIZE, (2, 1), 16, BI_RGB, &[], &[], &[0x00, 0x7C, 0x1F, 0x00]);
+        assert_eq!(decode(&data).pixels, [RED, BLUE]);
+    }
+
+    #[test]
+    fn bitfields_16_bit_565() {
+        let data = bitmap(INFO_HEADER_SIZE, (2, 1), 16, BI_BITFIELDS, &[0xF800, 0x07E0, 0x001F], &[], &[0xE0, 0x07, 0x10, 0x84]);
+        assert_eq!(decode(&data).pixels, [GREEN, 0xFF838183]);
+    }
+
+    #[test]
+    fn rgb_32_bit_without_alpha_is_opaque() {
+        let data = bitmap(INFO_HEADER_SIZE, (2, 1), 32, BI_RGB, &[], &[], &[0xFF, 0, 0, 0, 0x40, 0x80, 0xC0, 0]);
+        assert_eq!(decode(&data).pixels, [BLUE, 0xFFC08040]);
+    }
+
+    #[test]
+    fn v4_header_bitfields() {
+        let masks = [0x000000FF, 0x0000FF00, 0x00FF0000, 0];
+        let data = bitmap(V4_HEADER_SIZE, (1, 1), 32, BI_BITFIELDS, &masks, &[], &[0xFF, 0x80, 0, 0]);
+        assert_eq!(decode(&data).pixels, [0xFFFF8000]);
+    }
+
+    #[test]
+    fn v5_header_keeps_alpha() {
+        let masks = [0x00FF0000, 0x0000FF00, 0x000000FF, 0xFF000000];
+        let data = bitmap(V5_HEADER_SIZE, (2, 1), 32, BI_BITFIELDS, &masks, &[], &[0xFF, 0xFF, 0xFF, 0x80, 0, 0, 0, 0]);
+        assert_eq!(decode(&data).pixels, [0x80FFFFFF, 0]);
+    }
+
+    #[test]
+    fn full_width_mask() {
+        let data = bitmap(V3_HEADER_SIZE, (1, 1), 32, BI_BITFIELDS, &[0xFFFFFFFF, 0, 0, 0], &[], &[0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(decode(&data).pixels, [RED]);
+    }
+
+    #[test]
+    fn rle8() {
+        let runs = [
+            // Bottom row: an encoded run of 2, then an absolute run of 3 padded to 4 bytes, and an end of line
+            2, 1, 0, 3, 2, 0, 1, 0, 0, 0,
+            // A delta 2 right and 1 up skips the middle row, then a single pixel and the end of the bitmap
+            0, 2, 2, 1, 1, 2, 0, 1,
+        ];
+        let data = bitmap(INFO_HEADER_SIZE, (5, 3), 8, BI_RLE8, &[], &[RED, GREEN, BLUE], &runs);
+        assert_eq!(data.len() as u32, read_u32(&data, 2).unwrap());
+        assert_eq!(decode(&data).pixels, [
+            0, 0, BLUE, 0, 0,
+            0, 0, 0, 0, 0,
+            GREEN, GREEN, BLUE, RED, GREEN,
+        ]);
+    }
+
+    #[test]
+    fn rle4() {
+        let runs = [
+            // An encoded run of 3 alternating the two colors of its byte, then an absolute run of 3
+            3, 0x12, 0, 3, 0x30, 0x00, 0, 0,
+            // The second row is cut short by the end of the bitmap
+            1, 0x20, 0, 1,
+        ];
+        let data = bitmap(INFO_HEADER_SIZE, (6, 2), 4, BI_RLE4, &[], &[BLACK, RED, GREEN, BLUE], &runs);
+        assert_eq!(decode(&data).pixels, [
+            GREEN, 0, 0, 0, 0, 0,
+            RED, GREEN, RED, BLUE, BLACK, BLACK,
+        ]);
+    }
+
+    #[test]
+    fn invalid_signature() {
+        let mut data = bitmap(INFO_HEADER_SIZE, (1, 1), 24, BI_RGB, &[], &[], &[0; 4]);
+        data[..2].copy_from_slice(b"PN");
+        assert!(matches!(decode_bitmap(&data), Err(BitmapError::InvalidSignature(0x4E50))));
+    }
+
+    #[test]
+    fn unsupported_header() {
+        let data = bitmap(64, (1, 1), 24, BI_RGB, &[], &[], &[0; 4]);
+        assert!(matches!(decode_bitmap(&data), Err(BitmapError::UnsupportedHeader(64))));
+    }
+
+    #[test]
+    fn unsupported_bit_count() {
+        let data = bitmap(INFO_HEADER_SIZE, (1, 1), 2, BI_RGB, &[], &[BLACK; 4], &[0; 4]);
+        assert!(matches!(decode_bitmap(&data), Err(BitmapError::UnsupportedBitCount(2))));
+        let data = bitmap(INFO_HEADER_SIZE, (1, 1), 24, BI_RLE8, &[], &[], &[0, 1]);
+        assert!(matches!(decode_bitmap(&data), Err(BitmapError::UnsupportedBitCount(24))));
+    }
+
+    #[test]
+    fn unsupported_compression() {
+        // JPEG
+        let data = bitmap(INFO_HEADER_SIZE, (1, 1), 24, 4, &[], &[], &[0; 4]);
+        assert!(matches!(decode_bitmap(&data), Err(BitmapError::UnsupportedCompression(4))));
+    }
+
+    #[test]
+    fn invalid_dimensions() {
+        let data = bitmap(INFO_HEADER_SIZE, (0, 1), 24, BI_RGB, &[], &[], &[]);
+        assert!(matches!(decode_bitmap(&data), Err(BitmapError::InvalidDimensions(0, 1))));
+        let data = bitmap(INFO_HEADER_SIZE, (1, -16385), 24, BI_RGB, &[], &[], &[]);
+        assert!(matches!(decode_bitmap(&data), Err(BitmapError::InvalidDimensions(1, -16385))));
+    }
+
+    #[test]
+    fn invalid_color_index() {
+        let data = bitmap(INFO_HEADER_SIZE, (1, 1), 8, BI_RGB, &[], &[RED, GREEN], &[2, 0, 0, 0]);
+        assert!(matches!(decode_bitmap(&data), Err(BitmapError::InvalidColorIndex(2))));
+        let data = bitmap(INFO_HEADER_SIZE, (1, 1), 8, BI_RLE8, &[], &[RED], &[1, 5, 0, 1]);
+        assert!(matches!(decode_bitmap(&data), Err(BitmapError::InvalidColorIndex(5))));
+    }
+
+    #[test]
+    fn invalid_mask() {
+        // Gaps between the bits
+        let data = bitmap(INFO_HEADER_SIZE, (1, 1), 32, BI_BITFIELDS, &[0x00FF00FF, 0x0000FF00, 0], &[], &[0; 4]);
+        assert!(matches!(decode_bitmap(&data), Err(BitmapError::InvalidMask(0x00FF00FF))));
+        // Wider than the 16 bits of a pixel
+        let data = bitmap(INFO_HEADER_SIZE, (2, 1), 16, BI_BITFIELDS, &[0x1F0000, 0x07E0, 0x001F], &[], &[0; 4]);
+        assert!(matches!(decode_bitmap(&data), Err(BitmapError::InvalidMask(0x1F0000))));
+    }
+
+    #[test]
+    fn truncated() {
+        let data = bitmap(INFO_HEADER_SIZE, (2, 2), 24, BI_RGB, &[], &[], &[0; 12]);
+        assert!(matches!(decode_bitmap(&data), Err(BitmapError::Truncated)));
+        // The palette of 256 colors is cut short
+        let data = bitmap(INFO_HEADER_SIZE, (1, 1), 8, BI_RGB, &[], &[], &[0; 4]);
+        assert!(matches!(decode_bitmap(&data), Err(BitmapError::Truncated)));
+        // RLE data without an end of bitmap
+        let data = bitmap(INFO_HEADER_SIZE, (4, 1), 8, BI_RLE8, &[], &[RED], &[4, 0, 0]);
+        assert!(matches!(decode_bitmap(&data), Err(BitmapError::Truncated)));
+        assert!(matches!(decode_bitmap(&data[..20]), Err(BitmapError::Truncated)));
+    }
+
+    #[test]
+    fn huge_dimensions_in_small_file() {
+        let data = bitmap(INFO_HEADER_SIZE, (16384, 16384), 32, BI_RGB, &[], &[], &[0; 8]);
+        assert!(matches!(decode_bitmap(&data), Err(BitmapError::Truncated)));
+        let data = bitmap(INFO_HEADER_SIZE, (16384, 16384), 8, BI_RLE8, &[], &[RED], &[0, 1]);
+        assert!(matches!(decode_bitmap(&data), Err(BitmapError::Truncated)));
+    }
+}
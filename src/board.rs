@@ -1,202 +1,482 @@
-use std::{time::Instant, usize};
+use std::{collections::VecDeque, time::Duration};
+
+use rand::Rng;
+
+use crate::{action::GameAction, game::{GameState, State}, randomizer::Randomizer, renderer::{Block, Renderer}, scoring::{detect_t_spin, Lock, Spin}, tetromino::{Representation, Rotation, Tetromino, TetrominoGenertor, TetrominoKind}};
+
+// Rows right above the visible field where new tetrominos spawn
+pub const SPAWN_ROWS: usize = 2;
+pub const MIN_FIELD_SIZE: FieldSize = FieldSize { width: 4, height: 4 };
+// Largest field that still fits on screen next to the sidebar
+pub const MAX_FIELD_SIZE: FieldSize = FieldSize { width: 20, height: 40 };
+const EMPTY_BLOCK: BlockState = BlockState { set: false, block: Block::Empty };
+// Steps a tetromino dims through as its lock delay runs out, so the block cache stays small
+pub const LOCK_DIM_STEPS: u8 = 8;
+
+/// How long a grounded tetromino waits before settling, and how many times
+/// moving or rotating it may restart that wait
+#[derive(Debug, Copy, Clone)]
+pub struct LockDelay {
+    pub delay: Duration,
+    pub max_resets: u32,
+}
 
-use minifb::Key;
+impl Default for LockDelay {
+    fn default() -> Self {
+        Self {
+            delay: Duration::from_millis(500),
+            max_resets: 15,
+        }
+    }
+}
 
-use crate::{tetromino::{Tetromino, TetrominoGenertor}, GameState, State};
+/// Size of the visible part of the field in blocks, without the border
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FieldSize {
+    pub width: usize,
+    pub height: usize,
+}
 
-const BOARD_WIDTH: usize = 12;
-const BOARD_HEIGHT: usize = 22;
+impl Default for FieldSize {
+    fn default() -> Self {
+        Self { width: 10, height: 20 }
+    }
+}
 
 pub struct Board {
-    x: u32,
-    y: u32,
-    // BOARD_WIDTH and BOARD_HEIGHT include the border but we don't need state for those
-    state: [[BlockState; BOARD_WIDTH - 2 as usize]; BOARD_HEIGHT - 2 as usize],
+    size: FieldSize,
+    // Rows of the field from the top, a hidden buffer as tall as the visible field comes first
+    // so the stack can be pushed above the visible area without topping out
+    state: Vec<Vec<BlockState>>,
     tetromino_generator: TetrominoGenertor,
     current_tetromino: Option<Tetromino>,
-    current_tetromino_x: u32,
-    current_tetromino_y: u32,
-    last_tick: Instant,
+    // Position of the current tetromino in field coordinates, row 0 is the top hidden row
+    current_tetromino_x: i32,
+    current_tetromino_y: i32,
+    // Set once the current tetromino came from a hold, cleared when a tetromino settles
+    hold_used: bool,
+    // Kick used by the last rotation, cleared as soon as the tetromino moves
+    last_kick: Option<(i32, i32)>,
+    // Fraction of a row the current tetromino has fallen since it last moved down
+    gravity_progress: f32,
+    lock_delay: LockDelay,
+    // Time spent on the stack since the last lock reset, None while the current tetromino is falling
+    lock_timer: Option<Duration>,
+    lock_resets: u32,
+    // Lowest row the current tetromino reached, falling below it gives back all lock resets
+    lowest_row: i32,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 struct BlockState {
     set: bool,
-    color: u32,
+    // Block::Garbage for garbage rows, the tetromino it came from otherwise
+    block: Block,
 }
 
 impl Board {
-    pub fn new(x: u32, y: u32) -> Self {
-        let tetromino_generator = TetrominoGenertor::new();
-        let state = [[BlockState {set: false, color: 0}; BOARD_WIDTH - 2 as usize]; BOARD_HEIGHT - 2 as usize];
+    pub fn new(size: FieldSize, randomizer: Box<dyn Randomizer>, preview_count: usize, lock_delay: LockDelay) -> Self {
+        let tetromino_generator = TetrominoGenertor::new(randomizer, preview_count);
+        let state = vec![vec![EMPTY_BLOCK; size.width]; size.height * 2];
 
         Self {
-            x,
-            y,
+            size,
             state,
             tetromino_generator,
             current_tetromino: None,
             current_tetromino_x: 0,
             current_tetromino_y: 0,
-            last_tick: Instant::now(),
+            hold_used: false,
+            last_kick: None,
+            gravity_progress: 0.0,
+            lock_delay,
+            lock_timer: None,
+            lock_resets: 0,
+            lowest_row: 0,
         }
     }
 
-    pub fn update(&mut self, gs: &mut GameState, down_keys: &Vec<Key>) {
-        self.process_input(gs, down_keys);
-        self.drop_tetromino(gs);
+    pub fn reset(&mut self, gs: &mut GameState) {
+        self.state = vec![vec![EMPTY_BLOCK; self.size.width]; self.rows()];
+        self.current_tetromino = None;
+        self.hold_used = false;
+        gs.held_tetromino = None;
     }
 
-    pub fn render(&self, gs: &mut GameState) {
-        for px in 0..BOARD_WIDTH as u32 {
-            gs.display.draw_block(0xFF999999, self.x + px, self.y);
-            gs.display.draw_block(0xFF999999, self.x + px, self.y + (BOARD_HEIGHT as u32) - 1);
+    pub fn next_tetrominos(&self) -> &VecDeque<Tetromino> {
+        self.tetromino_generator.queue()
+    }
+
+    pub fn update(&mut self, gs: &mut GameState, dt: Duration, actions: &[GameAction], soft_drop_factor: f32) {
+        self.process_input(gs, actions);
+        if gs.state == State::Playing {
+            let gravity_factor = if actions.contains(&GameAction::SoftDrop) { soft_drop_factor } else { 1.0 };
+            self.drop_tetromino(gs, dt, gravity_factor);
         }
+    }
 
-        for py in 1..BOARD_HEIGHT as u32 {
-            gs.display.draw_block(0xFF999999, self.x, self.y + py);
-            gs.display.draw_block(0xFF999999, self.x + (BOARD_WIDTH as u32) - 1, self.y + py);
+    /// Pushes the stack up and fills the bottom with `count` garbage rows, each with a hole in a random column.
+    /// The current tetromino is pushed up too when the garbage reaches it. Returns false when blocks
+    /// get pushed out of the top of the field or the current tetromino has no room left.
+    pub fn add_garbage_rows(&mut self, count: usize, rng: &mut impl Rng) -> bool {
+        let rows = self.rows();
+        let count = count.min(rows);
+        let pushed_out = (0..count).any(|row| !self.is_line_empty(row));
+
+        self.state.rotate_left(count);
+        for row in rows - count..rows {
+            let hole = rng.gen_range(0..self.size.width);
+            for col in 0..self.size.width {
+                self.state[row][col] = if col == hole {
+                    EMPTY_BLOCK
+                } else {
+                    BlockState { set: true, block: Block::Garbage }
+                };
+            }
         }
 
-        for h in 0..(BOARD_HEIGHT - 2) as u32 {
-            for w in 0..(BOARD_WIDTH - 2) as u32 {
-                let state = self.state[h as usize][w as usize];
-                let x = w + self.x + 1;
-                let y = h + self.y + 1;
-                gs.display.draw_block(state.color, x, y);
+        for _ in 0..count {
+            if !self.detect_collision(self.current_tetromino_x, self.current_tetromino_y) {
+                break;
             }
+            self.current_tetromino_y -= 1;
+            self.lowest_row -= 1;
         }
 
-        if let Some(tetromino) = &self.current_tetromino {
-            tetromino.render(self.current_tetromino_x, self.current_tetromino_y, &mut gs.display);
-            
-            let mut y = self.current_tetromino_y;
-            loop { 
-                y += 1;
-                if self.detect_collision(self.current_tetromino_x, y) {
-                    break;
+        !pushed_out && !self.detect_collision(self.current_tetromino_x, self.current_tetromino_y)
+    }
+
+    /// Rows still holding garbage blocks
+    pub fn garbage_rows(&self) -> usize {
+        self.state.iter().filter(|row| row.iter().any(|state| state.block == Block::Garbage)).count()
+    }
+
+    /// Width and height of the board as drawn, border included
+    pub fn render_size(&self) -> (u32, u32) {
+        (self.size.width as u32 + 2, self.size.height as u32 + 2)
+    }
+
+    /// Draws the board with its border, its top left corner at x, y
+    pub fn render(&self, renderer: &mut dyn Renderer, x: u32, y: u32) {
+        self.render_border(renderer, x, y);
+
+        for row in self.hidden_rows()..self.rows() {
+            for col in 0..self.size.width {
+                let state = self.state[row][col];
+                if let Some((screen_x, screen_y)) = self.to_screen(x, y, col as i32, row as i32) {
+                    renderer.draw_block(state.block, screen_x, screen_y);
                 }
             }
+        }
 
-            if self.current_tetromino_y != y - 1 {
-                tetromino.render_shadow(self.current_tetromino_x, y - 1, &mut gs.display);
+        if let Some(tetromino) = &self.current_tetromino {
+            if !self.detect_collision(self.current_tetromino_x, self.current_tetromino_y) {
+                let ghost_y = self.drop_position();
+                if self.current_tetromino_y != ghost_y {
+                    self.render_cells(renderer, x, y, Block::Ghost(tetromino.kind()), ghost_y);
+                }
             }
+
+            let step = (self.lock_progress() * LOCK_DIM_STEPS as f32).floor() as u8;
+            let block = if step == 0 { Block::Piece(tetromino.kind()) } else { Block::Locking(tetromino.kind(), step) };
+            self.render_cells(renderer, x, y, block, self.current_tetromino_y);
+        }
+    }
+
+    /// Draws only the border, leaving the field empty
+    pub fn render_border(&self, renderer: &mut dyn Renderer, x: u32, y: u32) {
+        let (width, height) = self.render_size();
+        for px in 0..width {
+            renderer.draw_block(Block::Border, x + px, y);
+            renderer.draw_block(Block::Border, x + px, y + height - 1);
+        }
+
+        for py in 1..height {
+            renderer.draw_block(Block::Border, x, y + py);
+            renderer.draw_block(Block::Border, x + width - 1, y + py);
+        }
+    }
+
+    /// Places the next tetromino at the top of the field.
+    /// Returns false when it overlaps settled blocks (block out).
+    pub fn place_new_tetromino(&mut self) -> bool {
+        let tetromino = self.tetromino_generator.next_tetromino();
+        self.spawn_tetromino(tetromino)
+    }
+
+    fn spawn_tetromino(&mut self, tetromino: Tetromino) -> bool {
+        self.current_tetromino_x = (self.size.width as i32 - tetromino.size() as i32) / 2;
+        self.current_tetromino_y = (self.hidden_rows() - SPAWN_ROWS) as i32;
+        self.current_tetromino = Some(tetromino);
+        self.last_kick = None;
+        self.gravity_progress = 0.0;
+        self.lock_timer = None;
+        self.lock_resets = 0;
+        if self.detect_collision(self.current_tetromino_x, self.current_tetromino_y) {
+            return false;
+        }
+
+        // Like the guideline, the tetromino drops a row right away when it can so it shows up in the visible field
+        if !self.detect_collision(self.current_tetromino_x, self.current_tetromino_y + 1) {
+            self.current_tetromino_y += 1;
         }
+        self.lowest_row = self.current_tetromino_y;
+        true
     }
 
-    pub fn place_new_tetromino(&mut self, gs: &mut GameState) {
-        let tetromino = &gs.next_tetromino.clone().unwrap_or(self.tetromino_generator.get_random_tetromino());
-        if let Some(representation) = tetromino.current_representation() {
-            self.current_tetromino_x = self.x + (BOARD_WIDTH as u32 / 2 - ((representation.width as f32/2.0).ceil() as u32));
-            self.current_tetromino_y = self.y + 1;
+    /// Swaps the current tetromino with the held one, or stashes it and spawns the next one
+    /// when nothing is held yet. Only one hold is allowed until the next tetromino settles.
+    fn hold_tetromino(&mut self, gs: &mut GameState) {
+        if self.hold_used {
+            return;
         }
 
-        self.current_tetromino = Some(tetromino.clone());
-        gs.next_tetromino = Some(self.tetromino_generator.get_random_tetromino());
+        let Some(mut tetromino) = self.current_tetromino.take() else { return };
+        tetromino.reset_rotation();
+        self.hold_used = true;
+
+        let spawned = match gs.held_tetromino.replace(tetromino) {
+            Some(held) => self.spawn_tetromino(held),
+            None => self.place_new_tetromino(),
+        };
+
+        if !spawned {
+            gs.state = State::GameOver;
+        }
     }
 
-    fn process_input(&mut self, gs: &mut GameState, down_keys: &Vec<Key>) {
-        down_keys.iter().for_each(|key|
-            match key {
-                Key::Left => {
-                    if self.current_tetromino_x > self.x + 1 {
-                        let next_x_position = self.current_tetromino_x - 1;
-                        if !self.detect_collision(next_x_position, self.current_tetromino_y) {
-                            self.current_tetromino_x -= 1;
-                        }
+    fn process_input(&mut self, gs: &mut GameState, actions: &[GameAction]) {
+        actions.iter().for_each(|action|
+            match action {
+                GameAction::MoveLeft => {
+                    let next_x_position = self.current_tetromino_x - 1;
+                    if !self.detect_collision(next_x_position, self.current_tetromino_y) {
+                        self.current_tetromino_x -= 1;
+                        self.last_kick = None;
+                        self.reset_lock_timer();
                     }
                 }
-                Key::Right => {
-                    let Some(tetromino) = &self.current_tetromino else { return };
-                    let Some(representation) = tetromino.current_representation() else { return };
-
-                    if self.current_tetromino_x + representation.width < self.x + BOARD_WIDTH as u32 - 1 {
-                        let next_x_position = self.current_tetromino_x + 1;
-                        if !self.detect_collision(next_x_position, self.current_tetromino_y) {
-                            self.current_tetromino_x += 1;
-                        }
+                GameAction::MoveRight => {
+                    let next_x_position = self.current_tetromino_x + 1;
+                    if !self.detect_collision(next_x_position, self.current_tetromino_y) {
+                        self.current_tetromino_x += 1;
+                        self.last_kick = None;
+                        self.reset_lock_timer();
                     }
                 }
-                Key::Up => {
-                    if let Some(tetromino) = &mut self.current_tetromino {
-                        tetromino.rotate();
-                        if let Some(representation) = tetromino.current_representation() {
-                            let width = representation.width;
-                            if self.current_tetromino_x + width > self.x + BOARD_WIDTH as u32 - 2 {
-                                self.current_tetromino_x = self.x + BOARD_WIDTH as u32 - 2 - width;
-                            }
-                        }
-                    }
+                GameAction::RotateClockwise => {
+                    self.rotate_tetromino(Rotation::Clockwise);
+                }
+                GameAction::RotateCounterClockwise => {
+                    self.rotate_tetromino(Rotation::CounterClockwise);
                 }
-                Key::Down => {
-                    gs.state = State::DropBlock;
+                GameAction::Rotate180 => {
+                    self.rotate_tetromino(Rotation::Half);
                 }
-                _ => {}
+                GameAction::HardDrop => {
+                    self.hard_drop(gs);
+                }
+                GameAction::Hold => {
+                    self.hold_tetromino(gs);
+                }
+                GameAction::SoftDrop | GameAction::Pause => {}
             });
 
     }
 
-    fn drop_tetromino(&mut self, gs: &mut GameState) {
-        if gs.state != State::DropBlock && self.last_tick.elapsed().as_millis() < 300 {
-            return;
-        }
-
+    fn drop_tetromino(&mut self, gs: &mut GameState, dt: Duration, gravity_factor: f32) {
         if self.current_tetromino.is_none() {
             return;
         }
 
-        loop {
-            if !self.detect_collision(self.current_tetromino_x, self.current_tetromino_y + 1) {
-                self.current_tetromino_y += 1;
-                self.last_tick = Instant::now();
-                if gs.state != State::DropBlock {
-                    return;
-                }
-            } else {
+        let gravity = gs.level.gravity() * gravity_factor;
+
+        // Gravity accumulates fractions of a row between frames so slow levels fall smoothly
+        self.gravity_progress += dt.as_secs_f32() * gravity;
+
+        let mut rows = 0;
+        while self.gravity_progress >= 1.0 {
+            self.gravity_progress -= 1.0;
+            if self.detect_collision(self.current_tetromino_x, self.current_tetromino_y + 1) {
+                self.gravity_progress = 0.0;
                 break;
             }
+
+            self.move_down();
+            rows += 1;
+        }
+
+        if gravity_factor > 1.0 {
+            gs.scoring.soft_drop(rows);
         }
 
-        if gs.state == State::DropBlock {
-            gs.state = State::Playing;
+        if !self.detect_collision(self.current_tetromino_x, self.current_tetromino_y + 1) {
+            self.lock_timer = None;
             return;
         }
 
+        let lock_elapsed = match self.lock_timer {
+            Some(elapsed) => elapsed + dt,
+            None => Duration::ZERO,
+        };
+        self.lock_timer = Some(lock_elapsed);
+
+        if lock_elapsed >= self.lock_delay.delay {
+            self.lock_tetromino(gs, 0);
+        }
+    }
+
+    fn hard_drop(&mut self, gs: &mut GameState) {
+        if self.current_tetromino.is_none() {
+            return;
+        }
+
+        let mut rows = 0;
+        while self.current_tetromino_y < self.drop_position() {
+            self.move_down();
+            rows += 1;
+        }
+
+        self.lock_tetromino(gs, rows);
+    }
+
+    // Row the current tetromino would land on if dropped straight down
+    fn drop_position(&self) -> i32 {
+        let mut y = self.current_tetromino_y;
+        while !self.detect_collision(self.current_tetromino_x, y + 1) {
+            y += 1;
+        }
+
+        y
+    }
+
+    fn move_down(&mut self) {
+        self.current_tetromino_y += 1;
+        self.last_kick = None;
+        if self.current_tetromino_y > self.lowest_row {
+            self.lowest_row = self.current_tetromino_y;
+            self.lock_resets = 0;
+        }
+    }
+
+    /// Restarts the lock delay of a grounded tetromino after it moved or rotated,
+    /// until it runs out of resets
+    fn reset_lock_timer(&mut self) {
+        if self.lock_timer.is_some() && self.lock_resets < self.lock_delay.max_resets {
+            self.lock_timer = Some(Duration::ZERO);
+            self.lock_resets += 1;
+        }
+    }
+
+    // How far along the lock delay is, from 0 while falling to 1 when about to settle
+    fn lock_progress(&self) -> f32 {
+        let Some(lock_elapsed) = self.lock_timer else { return 0.0 };
+        if self.lock_delay.delay.is_zero() {
+            return 1.0;
+        }
+
+        (lock_elapsed.as_secs_f32() / self.lock_delay.delay.as_secs_f32()).min(1.0)
+    }
+
+    fn lock_tetromino(&mut self, gs: &mut GameState, hard_drop_rows: u32) {
+        // A tetromino that settles entirely in the hidden rows ends the game (lock out)
+        let locked_out = self.is_above_field();
+        let spin = self.detect_spin();
         self.settle_tetromino();
-        let line_erased = self.remove_full_line();
+        self.current_tetromino = None;
+        self.hold_used = false;
 
-        if line_erased > 0 {
-            gs.state = State::UpdateScore(line_erased);
+        if locked_out {
+            gs.state = State::GameOver;
+            return;
         }
-        else {
-            gs.state = State::NewTetrominoNeeded;
+
+        let lines = self.remove_full_line() as u32;
+        gs.state = State::UpdateScore(Lock { lines, spin, hard_drop_rows });
+    }
+
+    fn detect_spin(&self) -> Spin {
+        let Some(tetromino) = &self.current_tetromino else { return Spin::None };
+        let Some(kick) = self.last_kick else { return Spin::None };
+        if tetromino.kind() != TetrominoKind::T {
+            return Spin::None;
         }
+
+        detect_t_spin(tetromino.rotation(), kick, |x, y| {
+            self.is_occupied(self.current_tetromino_x + x, self.current_tetromino_y + y)
+        })
     }
 
-    fn detect_collision(&self, x: u32, y: u32) -> bool {
+    /// Rotates the current tetromino, trying each kick offset in turn.
+    /// The rotation is rejected if every kicked position collides.
+    fn rotate_tetromino(&mut self, rotation: Rotation) -> bool {
+        let Some(tetromino) = &self.current_tetromino else { return false };
+
+        let mut rotated = tetromino.clone();
+        rotated.rotate(rotation);
+        let Some(representation) = rotated.current_representation() else { return false };
+
+        for (kick_x, kick_y) in tetromino.kicks(tetromino.rotation(), rotated.rotation()) {
+            let x = self.current_tetromino_x + kick_x;
+            let y = self.current_tetromino_y + kick_y;
+            if !self.collides(representation, x, y) {
+                self.current_tetromino = Some(rotated);
+                self.current_tetromino_x = x;
+                self.current_tetromino_y = y;
+                self.last_kick = Some((kick_x, kick_y));
+                self.reset_lock_timer();
+                return true;
+            }
+        }
+
+        false
+    }
+
+    fn detect_collision(&self, x: i32, y: i32) -> bool {
         let Some(tetromino) = &self.current_tetromino else { return false };
         let Some(representation) = tetromino.current_representation() else { return false };
 
-        let vertices = &representation.vertices;
+        self.collides(representation, x, y)
+    }
+
+    fn collides(&self, representation: &Representation, x: i32, y: i32) -> bool {
+        representation.vertices.iter()
+            .any(|&(vertex_x, vertex_y)| self.is_occupied(x + vertex_x as i32, y + vertex_y as i32))
+    }
 
-        if y + representation.height >= self.y + BOARD_HEIGHT as u32 {
+    // Cells outside of the field count as occupied
+    fn is_occupied(&self, col: i32, row: i32) -> bool {
+        if col < 0 || col >= self.size.width as i32 || row < 0 || row >= self.rows() as i32 {
             return true
         }
 
-        for &(vertex_x, vertex_y) in vertices {
-            let row = (y + vertex_y) - self.y - 1;
-            let col = (x + vertex_x) - self.x - 1;
-            if self.state[row as usize][col as usize].set {
-                return true
+        self.state[row as usize][col as usize].set
+    }
+
+    fn is_above_field(&self) -> bool {
+        let Some(tetromino) = &self.current_tetromino else { return false };
+        let Some(representation) = tetromino.current_representation() else { return false };
+
+        representation.vertices.iter()
+            .all(|&(_, vertex_y)| self.current_tetromino_y + (vertex_y as i32) < self.hidden_rows() as i32)
+    }
+
+    // Draws the current tetromino at row, board_x and board_y being where the board is drawn
+    fn render_cells(&self, renderer: &mut dyn Renderer, board_x: u32, board_y: u32, block: Block, row: i32) {
+        let Some(tetromino) = &self.current_tetromino else { return };
+        let Some(representation) = tetromino.current_representation() else { return };
+
+        for &(vertex_x, vertex_y) in &representation.vertices {
+            let col = self.current_tetromino_x + vertex_x as i32;
+            if let Some((screen_x, screen_y)) = self.to_screen(board_x, board_y, col, row + vertex_y as i32) {
+                renderer.draw_block(block, screen_x, screen_y);
             }
         }
-
-        false
     }
 
-    fn remove_full_line(&mut self) -> i32 { 
+    fn remove_full_line(&mut self) -> i32 {
         let mut line_reset_count = 0;
-        for row in (0..BOARD_HEIGHT-2).rev() {
+        for row in (0..self.rows()).rev() {
             if !self.is_line_full(row) {
                 continue;
             }
@@ -216,48 +496,32 @@ impl Board {
         let Some(tetromino) = &self.current_tetromino else { return };
         let Some(representation) = tetromino.current_representation() else { return };
 
-        let vertices = &representation.vertices;
-        for &(vertex_x, vertex_y) in vertices {
-            let row = (self.current_tetromino_y + vertex_y) - self.y - 1;
-            let col = (self.current_tetromino_x + vertex_x) - self.x - 1;
-            self.state[row as usize][col as usize].set = true;
-            self.state[row as usize][col as usize].color = representation.color;
+        for &(vertex_x, vertex_y) in &representation.vertices {
+            let row = (self.current_tetromino_y + vertex_y as i32) as usize;
+            let col = (self.current_tetromino_x + vertex_x as i32) as usize;
+            self.state[row][col] = BlockState { set: true, block: Block::Piece(tetromino.kind()) };
         }
     }
 
     fn is_line_empty(&self, row: usize) -> bool {
-        for col in 0..BOARD_WIDTH - 2 {
-            if self.state[row][col].set {
-                return false;
-            }
-        }
-
-        true
+        self.state[row].iter().all(|block| !block.set)
     }
 
     fn is_line_full(&self, row: usize) -> bool {
-        for col in 0..BOARD_WIDTH - 2 {
-            if !self.state[row as usize][col as usize].set {
-                return false;
-            }
-        }
-
-        true
+        self.state[row].iter().all(|block| block.set)
     }
 
     fn reset_line(&mut self, row: usize) {
-        for col in 0..BOARD_WIDTH - 2 {
-            self.state[row][col].set = false;
-            self.state[row][col].color = 0;
-        }
+        self.state[row].fill(EMPTY_BLOCK);
     }
 
     fn drop_lines(&mut self) {
-        for row in (0..BOARD_HEIGHT - 3).rev() {
+        let rows = self.rows();
+        for row in (0..rows - 1).rev() {
             let mut dst_row = row;
             let mut next_row = row + 1;
 
-            while next_row != BOARD_HEIGHT - 2 && self.is_line_empty(next_row) {
+            while next_row != rows && self.is_line_empty(next_row) {
                 dst_row = next_row;
                 next_row += 1;
             }
@@ -270,8 +534,282 @@ impl Board {
     }
 
     fn copy_line(&mut self, src: usize, dst: usize) {
-        for col in 0..BOARD_WIDTH - 2 {
-            self.state[dst][col] = self.state[src][col];
+        self.state[dst] = self.state[src].clone();
+    }
+
+    // Rows of the buffer above the visible field, never rendered
+    fn hidden_rows(&self) -> usize {
+        self.size.height
+    }
+
+    fn rows(&self) -> usize {
+        self.state.len()
+    }
+
+    // Screen position of a field cell for a board drawn at board_x, board_y, hidden rows have none
+    fn to_screen(&self, board_x: u32, board_y: u32, col: i32, row: i32) -> Option<(u32, u32)> {
+        let hidden_rows = self.hidden_rows() as i32;
+        if row < hidden_rows {
+            return None;
+        }
+
+        Some((board_x + 1 + col as u32, board_y + 1 + (row - hidden_rows) as u32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{level::Level, renderer::Recorder, scoring::Scoring, tetromino::RotationState};
+
+    // Deals the given tetrominos over and over
+    struct Sequence {
+        kinds: Vec<TetrominoKind>,
+        next: usize,
+    }
+
+    impl Randomizer for Sequence {
+        fn next_kind(&mut self) -> TetrominoKind {
+            let kind = self.kinds[self.next % self.kinds.len()];
+            self.next += 1;
+            kind
         }
     }
+
+    fn board(kinds: &[TetrominoKind], lock_delay: LockDelay) -> (Board, GameState) {
+        let randomizer = Box::new(Sequence { kinds: kinds.to_vec(), next: 0 });
+        let board = Board::new(FieldSize::default(), randomizer, 1, lock_delay);
+        let gs = GameState { state: State::Playing, held_tetromino: None, level: Level::new(1), scoring: Scoring::new() };
+        (board, gs)
+    }
+
+    // Sets every cell of row but the holes
+    fn fill_row(board: &mut Board, row: usize, holes: &[usize]) {
+        for col in (0..board.size.width).filter(|col| !holes.contains(col)) {
+            board.state[row][col] = BlockState { set: true, block: Block::Garbage };
+        }
+    }
+
+    fn bottom(board: &Board) -> usize {
+        board.rows() - 1
+    }
+
+    fn current(board: &Board) -> &Tetromino {
+        board.current_tetromino.as_ref().unwrap()
+    }
+
+    #[test]
+    fn spawn_drops_into_visible_field() {
+        let (mut board, _) = board(&[TetrominoKind::I], LockDelay::default());
+        assert!(board.place_new_tetromino());
+        assert_eq!((board.current_tetromino_x, board.current_tetromino_y), (3, 19));
+
+        let mut recorder = Recorder::default();
+        board.render(&mut recorder, 0, 0);
+        let drawn: Vec<_> = recorder.blocks.iter()
+            .filter(|(block, _, _)| *block == Block::Piece(TetrominoKind::I))
+            .map(|&(_, x, y)| (x, y))
+            .collect();
+        assert_eq!(drawn, [(4, 1), (5, 1), (6, 1), (7, 1)]);
+    }
+
+    #[test]
+    fn spawn_stays_hidden_when_the_field_is_full_below() {
+        let (mut board, mut gs) = board(&[TetrominoKind::I], LockDelay::default());
+        fill_row(&mut board, 20, &[0]);
+        assert!(board.place_new_tetromino());
+        assert_eq!(board.current_tetromino_y, 18);
+
+        // Settling there, entirely in the hidden rows, is a lock out
+        board.update(&mut gs, Duration::ZERO, &[GameAction::HardDrop], 1.0);
+        assert_eq!(gs.state, State::GameOver);
+    }
+
+    #[test]
+    fn block_out() {
+        let (mut board, _) = board(&[TetrominoKind::I], LockDelay::default());
+        fill_row(&mut board, 19, &[0]);
+        assert!(!board.place_new_tetromino());
+    }
+
+    #[test]
+    fn lock_delay() {
+        let lock_delay = LockDelay { delay: Duration::from_millis(500), max_resets: 15 };
+        let (mut board, mut gs) = board(&[TetrominoKind::I], lock_delay);
+        board.place_new_tetromino();
+        board.current_tetromino_y = board.drop_position();
+
+        // The delay starts on the first frame on the ground
+        for _ in 0..6 {
+            assert_eq!(gs.state, State::Playing);
+            board.update(&mut gs, Duration::from_millis(100), &[], 1.0);
+        }
+        assert_eq!(gs.state, State::UpdateScore(Lock { lines: 0, spin: Spin::None, hard_drop_rows: 0 }));
+    }
+
+    #[test]
+    fn lock_delay_resets_are_capped() {
+        let lock_delay = LockDelay { delay: Duration::from_millis(500), max_resets: 2 };
+        let (mut board, mut gs) = board(&[TetrominoKind::I], lock_delay);
+        board.place_new_tetromino();
+        board.current_tetromino_y = board.drop_position();
+        board.update(&mut gs, Duration::ZERO, &[], 1.0);
+
+        let moves = [GameAction::MoveLeft, GameAction::MoveRight];
+        for action in moves.iter().cycle().take(2) {
+            board.update(&mut gs, Duration::from_millis(400), &[*action], 1.0);
+            assert_eq!(gs.state, State::Playing);
+        }
+
+        // Out of resets, moving no longer restarts the delay
+        board.update(&mut gs, Duration::from_millis(400), &[GameAction::MoveLeft], 1.0);
+        assert!(matches!(gs.state, State::UpdateScore(_)));
+    }
+
+    #[test]
+    fn falling_gives_back_lock_resets() {
+        let lock_delay = LockDelay { delay: Duration::from_millis(500), max_resets: 1 };
+        let (mut board, mut gs) = board(&[TetrominoKind::I], lock_delay);
+        board.place_new_tetromino();
+        let (x, y) = (board.current_tetromino_x, board.current_tetromino_y);
+        board.lock_timer = Some(Duration::ZERO);
+        board.lock_resets = 1;
+
+        board.move_down();
+        assert_eq!(board.lock_resets, 0);
+        board.update(&mut gs, Duration::ZERO, &[GameAction::MoveRight], 1.0);
+        assert_eq!((board.current_tetromino_x, board.current_tetromino_y), (x + 1, y + 1));
+        assert_eq!(board.lock_resets, 1);
+    }
+
+    #[test]
+    fn single_line_clear_drops_the_rows_above() {
+        let (mut board, mut gs) = board(&[TetrominoKind::I], LockDelay::default());
+        let bottom = bottom(&board);
+        fill_row(&mut board, bottom, &[3, 4, 5, 6]);
+        fill_row(&mut board, bottom - 1, &[1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        board.place_new_tetromino();
+
+        board.update(&mut gs, Duration::ZERO, &[GameAction::HardDrop], 1.0);
+        assert_eq!(gs.state, State::UpdateScore(Lock { lines: 1, spin: Spin::None, hard_drop_rows: 19 }));
+        assert!(board.state[bottom][0].set);
+        assert!(board.state[bottom][1..].iter().all(|state| !state.set));
+        assert!(board.is_line_empty(bottom - 1));
+    }
+
+    #[test]
+    fn tetris() {
+        let (mut board, mut gs) = board(&[TetrominoKind::I], LockDelay::default());
+        let bottom = bottom(&board);
+        for row in bottom - 3..=bottom {
+            fill_row(&mut board, row, &[9]);
+        }
+        board.place_new_tetromino();
+
+        let mut actions = vec![GameAction::RotateClockwise];
+        actions.extend([GameAction::MoveRight; 4]);
+        actions.push(GameAction::HardDrop);
+        board.update(&mut gs, Duration::ZERO, &actions, 1.0);
+        assert!(matches!(gs.state, State::UpdateScore(Lock { lines: 4, spin: Spin::None, .. })));
+        assert!((0..board.rows()).all(|row| board.is_line_empty(row)));
+    }
+
+    #[test]
+    fn hold_once_per_tetromino() {
+        let (mut board, mut gs) = board(&[TetrominoKind::T, TetrominoKind::I, TetrominoKind::O], LockDelay::default());
+        board.place_new_tetromino();
+
+        board.update(&mut gs, Duration::ZERO, &[GameAction::RotateClockwise, GameAction::Hold], 1.0);
+        assert_eq!(current(&board).kind(), TetrominoKind::I);
+        let held = gs.held_tetromino.as_ref().unwrap();
+        assert_eq!((held.kind(), held.rotation()), (TetrominoKind::T, RotationState::Spawn));
+
+        // A second hold before the I settles does nothing
+        board.update(&mut gs, Duration::ZERO, &[GameAction::Hold], 1.0);
+        assert_eq!(current(&board).kind(), TetrominoKind::I);
+        assert_eq!(gs.held_tetromino.as_ref().unwrap().kind(), TetrominoKind::T);
+
+        board.update(&mut gs, Duration::ZERO, &[GameAction::HardDrop], 1.0);
+        gs.state = State::Playing;
+        board.place_new_tetromino();
+        board.update(&mut gs, Duration::ZERO, &[GameAction::Hold], 1.0);
+        assert_eq!(current(&board).kind(), TetrominoKind::T);
+        assert_eq!(gs.held_tetromino.as_ref().unwrap().kind(), TetrominoKind::O);
+    }
+
+    #[test]
+    fn wall_kick() {
+        let (mut board, mut gs) = board(&[TetrominoKind::T], LockDelay::default());
+        board.place_new_tetromino();
+        let mut actions = vec![GameAction::RotateClockwise];
+        actions.extend([GameAction::MoveLeft; 5]);
+        board.update(&mut gs, Duration::ZERO, &actions, 1.0);
+        assert_eq!(board.current_tetromino_x, -1);
+
+        // Pointing down doesn't fit against the wall, the second R->2 test moves it right
+        board.update(&mut gs, Duration::ZERO, &[GameAction::RotateClockwise], 1.0);
+        assert_eq!(current(&board).rotation(), RotationState::Reverse);
+        assert_eq!(board.current_tetromino_x, 0);
+        assert_eq!(board.last_kick, Some((1, 0)));
+    }
+
+    #[test]
+    fn floor_kick_on_half_turn() {
+        let (mut board, mut gs) = board(&[TetrominoKind::T], LockDelay::default());
+        board.place_new_tetromino();
+        board.current_tetromino_y = board.drop_position();
+        let y = board.current_tetromino_y;
+
+        board.update(&mut gs, Duration::ZERO, &[GameAction::Rotate180], 1.0);
+        assert_eq!(current(&board).rotation(), RotationState::Reverse);
+        assert_eq!(board.current_tetromino_y, y - 1);
+        assert_eq!(board.last_kick, Some((0, -1)));
+    }
+
+    #[test]
+    fn o_does_not_kick() {
+        let (mut board, mut gs) = board(&[TetrominoKind::O], LockDelay::default());
+        board.place_new_tetromino();
+        let position = (board.current_tetromino_x, board.current_tetromino_y);
+
+        board.update(&mut gs, Duration::ZERO, &[GameAction::RotateClockwise], 1.0);
+        assert_eq!((board.current_tetromino_x, board.current_tetromino_y), position);
+        assert_eq!(board.last_kick, Some((0, 0)));
+    }
+
+    #[test]
+    fn t_spin_double() {
+        let (mut board, mut gs) = board(&[TetrominoKind::T], LockDelay::default());
+        let bottom = bottom(&board);
+        fill_row(&mut board, bottom, &[4]);
+        fill_row(&mut board, bottom - 1, &[3, 4, 5]);
+        // Overhang over the slot, the T can only get under it by rotating
+        board.state[bottom - 2][3] = BlockState { set: true, block: Block::Garbage };
+        board.place_new_tetromino();
+        board.current_tetromino.as_mut().unwrap().rotate(Rotation::Clockwise);
+        board.current_tetromino_x = 3;
+        board.current_tetromino_y = bottom as i32 - 2;
+
+        board.update(&mut gs, Duration::ZERO, &[GameAction::RotateClockwise, GameAction::HardDrop], 1.0);
+        assert_eq!(gs.state, State::UpdateScore(Lock { lines: 2, spin: Spin::Full, hard_drop_rows: 0 }));
+        assert!(board.state[bottom][3].set);
+    }
+
+    #[test]
+    fn no_spin_after_moving() {
+        let (mut board, mut gs) = board(&[TetrominoKind::T], LockDelay::default());
+        let bottom = bottom(&board);
+        fill_row(&mut board, bottom, &[4, 5]);
+        fill_row(&mut board, bottom - 1, &[3, 4, 5, 6]);
+        board.state[bottom - 2][3] = BlockState { set: true, block: Block::Garbage };
+        board.place_new_tetromino();
+        board.current_tetromino.as_mut().unwrap().rotate(Rotation::Clockwise);
+        board.current_tetromino_x = 3;
+        board.current_tetromino_y = bottom as i32 - 2;
+
+        // Slid into the same slot after the rotation, the 3 corners are there but it is no spin
+        let actions = [GameAction::RotateClockwise, GameAction::MoveRight, GameAction::MoveLeft, GameAction::HardDrop];
+        board.update(&mut gs, Duration::ZERO, &actions, 1.0);
+        assert!(matches!(gs.state, State::UpdateScore(Lock { spin: Spin::None, .. })));
+    }
 }
@@ -0,0 +1,19 @@
+use std::time::{Duration, Instant};
+
+/// Measures the time that goes by between two frames, so the game never reads the system time itself
+pub struct SystemClock {
+    last_tick: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        Self { last_tick: Instant::now() }
+    }
+
+    pub fn tick(&mut self) -> Duration {
+        let now = Instant::now();
+        let dt = now.duration_since(self.last_tick);
+        self.last_tick = now;
+        dt
+    }
+}
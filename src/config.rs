@@ -0,0 +1,129 @@
+use std::{collections::HashMap, fs, io::{Error, ErrorKind}};
+
+/// Values a config file can hold, a small subset of TOML
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// A single value reads as an array of one, so `key = "A"` and `key = ["A"]` are the same
+    pub fn as_array(&self) -> Vec<&Value> {
+        match self {
+            Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        }
+    }
+}
+
+/// Sections of `key = value` lines, like
+///
+/// ```toml
+/// [controls]
+/// hold = ["C", "LeftShift"]
+/// ```
+#[derive(Debug, Default)]
+pub struct Config {
+    sections: HashMap<String, HashMap<String, Value>>,
+}
+
+impl Config {
+    /// Loads a config file, a missing file is an empty config
+    pub fn load(filename: &str) -> Result<Self, Error> {
+        match fs::read_to_string(filename) {
+            Ok(text) => Self::parse(&text).map_err(|error| Error::new(ErrorKind::InvalidData, format!("{}: {}", filename, error))),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(error) => Err(error),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut config = Self::default();
+        let mut section = String::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let line_error = |message: &str| Error::new(ErrorKind::InvalidData, format!("line {}: {}", index + 1, message));
+
+            if let Some(name) = line.strip_prefix('[') {
+                let Some(name) = name.strip_suffix(']') else { return Err(line_error("section should end with ]")) };
+                section = name.trim().to_string();
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else { return Err(line_error("expected key = value")) };
+            let mut chars = value.trim().chars().peekable();
+            let value = parse_value(&mut chars).map_err(|message| line_error(&message))?;
+            skip_whitespace(&mut chars);
+            if chars.next().is_some_and(|c| c != '#') {
+                return Err(line_error("unexpected characters after value"));
+            }
+
+            config.sections.entry(section.clone()).or_default().insert(key.trim().to_string(), value);
+        }
+
+        Ok(config)
+    }
+
+    pub fn section(&self, name: &str) -> Option<&HashMap<String, Value>> {
+        self.sections.get(name)
+    }
+}
+
+type Chars<'a> = std::iter::Peekable<std::str::Chars<'a>>;
+
+fn parse_value(chars: &mut Chars) -> Result<Value, String> {
+    skip_whitespace(chars);
+    match chars.next() {
+        Some('"') => {
+            let mut value = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => return Ok(Value::String(value)),
+                    Some('\\') => match chars.next() {
+                        Some('n') => value.push('\n'),
+                        Some(c) => value.push(c),
+                        None => return Err("unterminated string".to_string()),
+                    },
+                    Some(c) => value.push(c),
+                    None => return Err("unterminated string".to_string()),
+                }
+            }
+        }
+        Some('[') => {
+            let mut values = Vec::new();
+            loop {
+                skip_whitespace(chars);
+                if chars.next_if_eq(&']').is_some() {
+                    return Ok(Value::Array(values));
+                }
+
+                values.push(parse_value(chars)?);
+                skip_whitespace(chars);
+                match chars.next() {
+                    Some(',') => continue,
+                    Some(']') => return Ok(Value::Array(values)),
+                    _ => return Err("array values should be separated by ,".to_string()),
+                }
+            }
+        }
+        Some(c) => Err(format!("unsupported value starting with {}", c)),
+        None => Err("missing value".to_string()),
+    }
+}
+
+fn skip_whitespace(chars: &mut Chars) {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+}
@@ -1,142 +1,258 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, ops::Range, rc::Rc, time::{Duration, Instant}};
 
-use crate::bitmap::load_bitmap;
+use crate::{bitmap::Bitmap, renderer::{BlendMode, Block, Renderer}, theme::{Theme, BLOCK_SIZE, FONT_WIDTH}};
 
-const CLEAR_COLOR: u32 = 0x1D1D1D;
-const BLOCK_SIZE: usize = 16;
+const CLIPPED_COLOR: u32 = 0xFFFF00FF;
+const UNKNOWN_GLYPH: char = '?';
+// How often the files of the theme are checked for changes
+const THEME_CHECK_INTERVAL: Duration = Duration::from_millis(500);
 
 pub struct Display {
     pub width: usize,
     pub height: usize,
-    pub virtual_width: usize,
-    pub virtual_height: usize,
     pub pixel_size: usize,
     pub buffer: Vec<u32>,
+    /// Outlines what was cut off by the edges of the buffer, see draw_debug_overlay
+    pub show_clipping: bool,
+    // Draws that went past the edges of the buffer since the last overlay
+    clipped: Vec<Rect>,
+    theme: Theme,
+    theme_checked: Instant,
     block_factory: BlockFactory,
     glyph_cache: GlyphCache,
 }
 
 impl Display {
-    pub fn new(width: usize, height: usize, pixel_size: usize, buffer: Vec<u32>) -> Self {
+    pub fn new(width: usize, height: usize, pixel_size: usize, buffer: Vec<u32>, theme: Theme) -> Self {
         Self {
             width,
             height,
-            virtual_width: width / pixel_size,
-            virtual_height: height / pixel_size,
             pixel_size,
             buffer,
+            show_clipping: false,
+            clipped: Vec::new(),
             block_factory: BlockFactory::new(),
-            glyph_cache: GlyphCache::new(),
+            glyph_cache: GlyphCache::new(&theme.font),
+            theme,
+            theme_checked: Instant::now(),
         }
     }
 
-    pub fn clear_buffer(&mut self) {
-        for pixel in &mut self.buffer {
-            *pixel = CLEAR_COLOR;
-        }
+    pub fn theme(&self) -> &Theme {
+        &self.theme
     }
 
-    pub fn draw_block(&mut self, color: u32, x: u32, y: u32) {
-        assert!(x + (BLOCK_SIZE as u32 / self.pixel_size as u32) < self.virtual_width as u32 && y + (BLOCK_SIZE as u32 / self.pixel_size as u32) < self.virtual_height as u32, "Drawing block outside buffer boundaries");
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.block_factory = BlockFactory::new();
+        self.glyph_cache = GlyphCache::new(&theme.font);
+        self.theme = theme;
+    }
 
-        if let Some(block) = self.block_factory.get_block(color) {
-            for h in 0..BLOCK_SIZE {
-                for w in 0..BLOCK_SIZE {
-                    let src_index = (BLOCK_SIZE * h) + w;
-                    let dst_index = (self.width as u32 * (h as u32 + y * self.pixel_size as u32)) + (w as u32 + x * self.pixel_size as u32);
+    /// Loads the theme again when one of its files changed, keeping the current one if that fails
+    pub fn reload_theme_if_changed(&mut self) {
+        if self.theme_checked.elapsed() < THEME_CHECK_INTERVAL {
+            return;
+        }
+        self.theme_checked = Instant::now();
 
-                    self.buffer[dst_index as usize] = block[src_index as usize];
+        if self.theme.has_changed() {
+            match Theme::load(&self.theme.name) {
+                Ok(theme) => self.set_theme(theme),
+                Err(error) => {
+                    eprintln!("Could not reload the theme: {}", error);
+                    self.theme.mark_seen();
                 }
             }
-        } else { 
-            panic!("Couldn't create block") 
         }
     }
 
-    pub fn draw_text(&mut self, x: u32, y: u32, text: String) {
-        let mut char_x = x;
-        for c in text.to_uppercase().chars() {
-            if let Some(glyph) = self.glyph_cache.get_glyph(&c) {
-                for h in 0..BLOCK_SIZE {
-                    for w in 0..BLOCK_SIZE {
-                        let src_index = (BLOCK_SIZE * h) + w;
-                        let dst_index = (self.width as u32 * (h as u32 + y * self.pixel_size as u32)) + (w as u32 + char_x * self.pixel_size as u32);
+    /// Draws width x height pixels with their top left corner at pixel x, y of the buffer, whatever falls
+    /// outside of the buffer is cut off
+    pub fn blit(&mut self, src: &[u32], width: usize, height: usize, x: i32, y: i32, mode: BlendMode) {
+        assert!(src.len() == width * height, "Blitting {} pixels as {}x{}", src.len(), width, height);
+        let rect = Rect { x: x as i64, y: y as i64, width: width as i64, height: height as i64 };
+        let Some((columns, rows)) = rect.visible(self.width, self.height) else {
+            self.check_clipping(rect);
+            return;
+        };
+
+        for h in rows {
+            let src_row = &src[h * width + columns.start..h * width + columns.end];
+            let dst_start = (rect.y + h as i64) as usize * self.width + (rect.x + columns.start as i64) as usize;
+            for (dst, &pixel) in self.buffer[dst_start..dst_start + src_row.len()].iter_mut().zip(src_row) {
+                *dst = mode.apply(pixel, *dst);
+            }
+        }
+        self.check_clipping(rect);
+    }
+
+    pub fn clear_buffer(&mut self) {
+        for pixel in &mut self.buffer {
+            *pixel = self.theme.background;
+        }
+    }
+
+    /// Outlines the visible part of every draw that was clipped since the last call and counts them,
+    /// does nothing unless show_clipping is set
+    pub fn draw_debug_overlay(&mut self) {
+        let clipped = std::mem::take(&mut self.clipped);
+        if !self.show_clipping || clipped.is_empty() {
+            return;
+        }
 
-                        self.buffer[dst_index as usize] = glyph[src_index as usize];
-                    }
+        for rect in &clipped {
+            if let Some((columns, rows)) = rect.clip(self.width, self.height) {
+                let (left, right) = (columns.start as i64, columns.end as i64);
+                let (top, bottom) = (rows.start as i64, rows.end as i64);
+                for edge in [
+                    Rect { x: left, y: top, width: right - left, height: 1 },
+                    Rect { x: left, y: bottom - 1, width: right - left, height: 1 },
+                    Rect { x: left, y: top, width: 1, height: bottom - top },
+                    Rect { x: right - 1, y: top, width: 1, height: bottom - top },
+                ] {
+                    fill(&mut self.buffer, self.width, CLIPPED_COLOR, edge, BlendMode::Opaque);
                 }
-            } else { 
-                panic!("Writing text with unknown glyph") 
             }
+        }
+        self.draw_text(0, 0, format!("CLIPPED {}", clipped.len()));
+        self.clipped.clear();
+    }
+
+    // Converts a position in blocks to pixels, positions too far to fit end up past the right or bottom edge anyway
+    fn to_pixels(&self, position: u32) -> i32 {
+        (position as i64 * self.pixel_size as i64).min(i32::MAX as i64) as i32
+    }
 
-            char_x += 1;
+    fn check_clipping(&mut self, rect: Rect) {
+        if self.show_clipping && !rect.is_inside(self.width, self.height) {
+            self.clipped.push(rect);
         }
     }
 }
 
-struct BlockFactory {
-    cache: HashMap<u32, Vec<u32>>,
-    block_template: Vec<u32>
+impl Renderer for Display {
+    fn draw_block(&mut self, block: Block, x: u32, y: u32) {
+        let pixels = self.block_factory.get_block(&self.theme, block);
+        self.blit(&pixels, BLOCK_SIZE, BLOCK_SIZE, self.to_pixels(x), self.to_pixels(y), BlendMode::Alpha);
+    }
+
+    fn draw_text(&mut self, x: u32, y: u32, text: String) {
+        for (i, c) in text.to_uppercase().chars().enumerate() {
+            let glyph = self.glyph_cache.get_glyph(&c);
+            let char_x = self.to_pixels(x.saturating_add(i as u32));
+            self.blit(&glyph, BLOCK_SIZE, BLOCK_SIZE, char_x, self.to_pixels(y), BlendMode::Alpha);
+        }
+    }
+
+    fn fill_rect(&mut self, color: u32, x: u32, y: u32, width: u32, height: u32, mode: BlendMode) {
+        let pixel_size = self.pixel_size as i64;
+        let rect = Rect { x: self.to_pixels(x) as i64, y: self.to_pixels(y) as i64, width: width as i64 * pixel_size, height: height as i64 * pixel_size };
+        fill(&mut self.buffer, self.width, color, rect, mode);
+        self.check_clipping(rect);
+    }
 }
 
-impl BlockFactory {
-    fn new() -> Self {
-        let template = match load_bitmap("./block.bmp") {
-            Ok(bitmap) => bitmap,
-            Err(error) => panic!("{}", error)
+impl BlendMode {
+    fn apply(self, src: u32, dst: u32) -> u32 {
+        let alpha = src >> 24;
+        if self == BlendMode::Opaque || (self == BlendMode::Alpha && alpha == 0xFF) {
+            return src;
+        }
+        if alpha == 0 {
+            return dst;
+        }
+
+        let channel = |shift: u32| {
+            let (s, d) = ((src >> shift) & 0xFF, (dst >> shift) & 0xFF);
+            let blended = match self {
+                BlendMode::Additive => (d + s).min(0xFF),
+                BlendMode::Multiply => d * s / 0xFF,
+                _ => s,
+            };
+            ((blended * alpha + d * (0xFF - alpha)) / 0xFF) << shift
         };
-        assert!(template.len() == BLOCK_SIZE * BLOCK_SIZE, "Block bitmap should be 16x16");
 
-        Self {
-            cache: HashMap::new(),
-            block_template: template
-        }
+        (dst & 0xFF000000) | channel(16) | channel(8) | channel(0)
     }
+}
+
+/// Pixels covered by a draw, which can reach past any edge of the buffer
+#[derive(Debug, Copy, Clone)]
+struct Rect {
+    x: i64,
+    y: i64,
+    width: i64,
+    height: i64,
+}
 
-    fn get_block(&mut self, color: u32) -> Option<&Vec<u32>> {
-        if !self.cache.contains_key(&color) {
-            let new_block = self.create_colored_block(color);
-            self.cache.insert(color, new_block);
+impl Rect {
+    fn is_inside(&self, width: usize, height: usize) -> bool {
+        self.x >= 0 && self.y >= 0 && self.x + self.width <= width as i64 && self.y + self.height <= height as i64
+    }
+
+    // Columns and rows of the rectangle, relative to its top left corner, that are inside the buffer
+    fn visible(&self, width: usize, height: usize) -> Option<(Range<usize>, Range<usize>)> {
+        let left = (-self.x).clamp(0, self.width);
+        let right = (width as i64 - self.x).clamp(0, self.width);
+        let top = (-self.y).clamp(0, self.height);
+        let bottom = (height as i64 - self.y).clamp(0, self.height);
+        if left >= right || top >= bottom {
+            return None;
         }
 
-        self.cache.get(&color)
+        Some((left as usize..right as usize, top as usize..bottom as usize))
+    }
+
+    // Columns and rows of the buffer covered by the rectangle
+    fn clip(&self, width: usize, height: usize) -> Option<(Range<usize>, Range<usize>)> {
+        let (columns, rows) = self.visible(width, height)?;
+        let (x, y) = (self.x as usize, self.y as usize);
+        Some((x.wrapping_add(columns.start)..x.wrapping_add(columns.end), y.wrapping_add(rows.start)..y.wrapping_add(rows.end)))
     }
+}
+
+// Draws color over rect, whatever falls outside of the buffer is cut off
+fn fill(buffer: &mut [u32], width: usize, color: u32, rect: Rect, mode: BlendMode) {
+    let Some((columns, rows)) = rect.clip(width, buffer.len() / width) else {
+        return;
+    };
 
-    fn create_colored_block(&self, color: u32) -> Vec<u32> {
-        let mut new_block = self.block_template.clone();
-        for pixel in &mut new_block {
-            let lum_r = ((*pixel >> 16) & 0xFF) as f32 / 255.0;
-            let lum_g = ((*pixel >> 8) & 0xFF) as f32 / 255.0;
-            let lum_b = (*pixel & 0xFF) as f32 / 255.0;
+    for y in rows {
+        for dst in &mut buffer[y * width + columns.start..y * width + columns.end] {
+            *dst = mode.apply(color, *dst);
+        }
+    }
+}
 
-            let new_r = (color >> 16) & 0xFF;
-            let new_g = (color >> 8) & 0xFF;
-            let new_b = color & 0xFF;
+// Pixels of each block drawn so far with the current theme
+struct BlockFactory {
+    cache: HashMap<Block, Rc<Vec<u32>>>,
+}
 
-            *pixel = ((new_r as f32 * lum_r) as u32) << 16 | ((new_g as f32 * lum_g) as u32) << 8 | (new_b as f32 * lum_b) as u32;
+impl BlockFactory {
+    fn new() -> Self {
+        Self {
+            cache: HashMap::new(),
         }
+    }
 
-        new_block
+    fn get_block(&mut self, theme: &Theme, block: Block) -> Rc<Vec<u32>> {
+        self.cache.entry(block).or_insert_with(|| Rc::new(theme.block_pixels(block))).clone()
     }
 }
 
 struct GlyphCache {
-    cache: HashMap<char, Vec<u32>>,
+    cache: HashMap<char, Rc<Vec<u32>>>,
 }
 
 impl GlyphCache {
-    fn new() -> Self {
-        const GLYPHS_WIDTH: u32 = 64;
-        const GLYPHS_HEIGHT: u32 = 80;
-        let keys: Vec<char> = vec!['1', '2', '3', '4', '5', '6', '7', '8', '9', '0', 'T', 'I', 'S', 'C', 'O', 'R', 'E', 'N', 'X', '!'];
-
-        let glyphs = match load_bitmap("./glyphs.bmp") {
-            Ok(bitmap) => bitmap,
-            Err(error) => panic!("{}", error)
-        };
-        assert!(glyphs.len() == (GLYPHS_WIDTH * GLYPHS_HEIGHT) as usize, "Glyphs bitmap should be 64x80");
+    // The font is FONT_WIDTH wide, its glyphs laid out row by row in the order of keys
+    fn new(font: &Bitmap) -> Self {
+        const GLYPHS_WIDTH: u32 = FONT_WIDTH;
+        let keys: Vec<char> = "1234567890TISCORENX!ABDFGHJKLMPQUVWYZ -.:/<>+%?'".chars().collect();
+        let glyphs = &font.pixels;
 
-        let mut cache: HashMap<char, Vec<u32>> = HashMap::with_capacity(keys.len());
+        let mut cache: HashMap<char, Rc<Vec<u32>>> = HashMap::with_capacity(keys.len());
 
         for (i, k) in keys.iter().enumerate() {
             let y = i as u32 / (GLYPHS_WIDTH / BLOCK_SIZE as u32);
@@ -149,11 +265,11 @@ impl GlyphCache {
                     let dst_index = (BLOCK_SIZE * h) + w;
                     let src_index = (GLYPHS_WIDTH * (h as u32 + (y * BLOCK_SIZE as u32))) + (w as u32 + (x * BLOCK_SIZE as u32));
 
-                    glyph[dst_index as usize] = glyphs[src_index as usize];
+                    glyph[dst_index] = glyphs[src_index as usize];
                 }
             }
 
-            cache.insert(*k, glyph);
+            cache.insert(*k, Rc::new(glyph));
         }
 
         Self {
@@ -161,7 +277,8 @@ impl GlyphCache {
         }
     }
 
-    fn get_glyph(&self, key: &char) -> Option<&Vec<u32>> {
-        self.cache.get(key)
+    // Characters the font has no glyph for, like the ones in theme names, show as ?
+    fn get_glyph(&self, key: &char) -> Rc<Vec<u32>> {
+        self.cache.get(key).unwrap_or(&self.cache[&UNKNOWN_GLYPH]).clone()
     }
 }
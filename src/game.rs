@@ -0,0 +1,305 @@
+use std::{collections::VecDeque, mem, time::Duration};
+
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::{action::GameAction, board::{Board, FieldSize, LockDelay}, level::Level, mode::Mode, randomizer::RandomizerKind, scoring::{ClearEvent, Lock, Scoring}, tetromino::Tetromino};
+
+const CLEAR_EVENT_DURATION: Duration = Duration::from_secs(2);
+
+pub struct GameState {
+    pub state: State,
+    pub held_tetromino: Option<Tetromino>,
+    pub level: Level,
+    pub scoring: Scoring,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum State {
+    NewGame,
+    Playing,
+    NewTetrominoNeeded,
+    UpdateScore(Lock),
+    GameOver,
+    // The mode's goal was reached
+    Finished,
+    // Holds the state to go back to once the game resumes
+    Paused(Box<State>),
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct GameSettings {
+    pub mode: Mode,
+    pub start_level: u32,
+    pub field_size: FieldSize,
+    // Lines to clear to finish a sprint
+    pub sprint_lines: u32,
+    // How long an ultra game lasts
+    pub ultra_time: Duration,
+    // Garbage rows a dig starts with, and the time between two new rows if they keep coming
+    pub dig_rows: u32,
+    pub dig_interval: Option<Duration>,
+    pub preview_count: usize,
+    pub lock_delay: LockDelay,
+    // Time between a tetromino settling and the next one spawning
+    pub entry_delay: Duration,
+    // Gravity is multiplied by this factor while soft dropping
+    pub soft_drop_factor: f32,
+}
+
+impl Default for GameSettings {
+    fn default() -> Self {
+        Self {
+            mode: Mode::Marathon,
+            start_level: 1,
+            field_size: FieldSize::default(),
+            sprint_lines: 40,
+            ultra_time: Duration::from_secs(120),
+            dig_rows: 10,
+            dig_interval: None,
+            preview_count: 5,
+            lock_delay: LockDelay::default(),
+            entry_delay: Duration::ZERO,
+            soft_drop_factor: 20.0,
+        }
+    }
+}
+
+/// The rules of the game without any window or rendering. Time only moves forward
+/// through `step`, so the same actions and durations always play out the same way.
+pub struct Game {
+    gs: GameState,
+    board: Board,
+    settings: GameSettings,
+    // Time elapsed since the game started, adding up the step durations
+    time: Duration,
+    // Time the last tetromino settled, the next one spawns once the entry delay went by
+    last_lock: Duration,
+    // Tetrominos settled since the game started
+    pieces: u32,
+    // Picks the holes of garbage rows
+    garbage_rng: StdRng,
+    // Time the last garbage row rose on its own
+    last_garbage: Duration,
+    last_clear_event: Option<(ClearEvent, Duration)>,
+}
+
+impl Game {
+    /// Starts a game whose tetrominos and garbage are all drawn from `seed`
+    pub fn new(settings: GameSettings, randomizer: RandomizerKind, seed: u64) -> Self {
+        Self {
+            gs: GameState {
+                state: State::NewGame,
+                held_tetromino: None,
+                level: Level::new(settings.start_level),
+                scoring: Scoring::new(),
+            },
+            board: Board::new(settings.field_size, randomizer.create(seed), settings.preview_count, settings.lock_delay),
+            settings,
+            time: Duration::ZERO,
+            last_lock: Duration::ZERO,
+            pieces: 0,
+            // Inverting the seed keeps the holes from following the tetromino sequence
+            garbage_rng: StdRng::seed_from_u64(!seed),
+            last_garbage: Duration::ZERO,
+            last_clear_event: None,
+        }
+    }
+
+    pub fn step(&mut self, dt: Duration, actions: &[GameAction]) {
+        // Game time stands still while paused, which freezes gravity, lock and entry delays
+        if self.is_paused() || self.is_over() {
+            return;
+        }
+        self.time += dt;
+
+        if let Some(limit) = self.settings.mode.time_limit(&self.settings) && self.time >= limit {
+            self.time = limit;
+            self.gs.state = State::Finished;
+            return;
+        }
+
+        if let Some(interval) = self.settings.mode.garbage_interval(&self.settings) && self.time - self.last_garbage >= interval {
+            self.last_garbage += interval;
+            if !self.board.add_garbage_rows(1, &mut self.garbage_rng) {
+                self.gs.state = State::GameOver;
+                return;
+            }
+        }
+
+        match self.gs.state {
+            State::NewGame => {
+                self.board.reset(&mut self.gs);
+                self.gs.level = Level::new(self.settings.start_level);
+                self.gs.scoring = Scoring::new();
+                self.time = Duration::ZERO;
+                self.last_lock = Duration::ZERO;
+                self.pieces = 0;
+                self.last_garbage = Duration::ZERO;
+                self.last_clear_event = None;
+                self.gs.state = if self.board.add_garbage_rows(self.settings.mode.start_garbage(&self.settings), &mut self.garbage_rng) {
+                    State::NewTetrominoNeeded
+                } else {
+                    State::GameOver
+                };
+            }
+            State::NewTetrominoNeeded if self.time - self.last_lock >= self.settings.entry_delay => {
+                self.gs.state = if self.board.place_new_tetromino() {
+                    State::Playing
+                } else {
+                    State::GameOver
+                };
+            }
+            State::UpdateScore(lock) => {
+                self.gs.state = State::NewTetrominoNeeded;
+                self.last_lock = self.time;
+                self.pieces += 1;
+                let event = self.gs.scoring.lock(lock, self.gs.level.level());
+                self.gs.level.add_lines(lock.lines);
+                if event.points > 0 {
+                    self.last_clear_event = Some((event, self.time));
+                }
+
+                if self.settings.mode.is_finished(self) {
+                    self.gs.state = State::Finished;
+                }
+            }
+            State::Playing => {
+                self.board.update(&mut self.gs, dt, actions, self.settings.soft_drop_factor);
+            }
+            _ => {},
+        }
+    }
+
+    /// Pauses a game in progress, a game that is over or paused already stays as it is
+    pub fn pause(&mut self) {
+        if !self.is_over() && !self.is_paused() {
+            let state = mem::replace(&mut self.gs.state, State::NewGame);
+            self.gs.state = State::Paused(Box::new(state));
+        }
+    }
+
+    pub fn resume(&mut self) {
+        if let State::Paused(state) = &mut self.gs.state {
+            let state = mem::replace(state.as_mut(), State::NewGame);
+            self.gs.state = state;
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        matches!(self.gs.state, State::Paused(_))
+    }
+
+    /// Whether the game ended, either topping out or reaching the goal of its mode
+    pub fn is_over(&self) -> bool {
+        matches!(self.gs.state, State::GameOver | State::Finished)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.gs.state == State::Finished
+    }
+
+    pub fn settings(&self) -> &GameSettings {
+        &self.settings
+    }
+
+    /// Time played, which stops while paused and once the game is over
+    pub fn time(&self) -> Duration {
+        self.time
+    }
+
+    pub fn pieces(&self) -> u32 {
+        self.pieces
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn held_tetromino(&self) -> Option<&Tetromino> {
+        self.gs.held_tetromino.as_ref()
+    }
+
+    pub fn next_tetrominos(&self) -> &VecDeque<Tetromino> {
+        self.board.next_tetrominos()
+    }
+
+    pub fn level(&self) -> &Level {
+        &self.gs.level
+    }
+
+    pub fn scoring(&self) -> &Scoring {
+        &self.gs.scoring
+    }
+
+    /// The last clear that scored points, for a little while after it happened
+    pub fn clear_event(&self) -> Option<&ClearEvent> {
+        match &self.last_clear_event {
+            Some((event, time)) if self.time - *time < CLEAR_EVENT_DURATION => Some(event),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{renderer::{Block, Recorder}, view::render_game};
+
+    const FRAME: Duration = Duration::from_millis(16);
+
+    // Hard drops every tetromino where it spawns until the game ends
+    fn play_until_over(seed: u64) -> Game {
+        let mut game = Game::new(GameSettings::default(), RandomizerKind::SevenBag, seed);
+        for _ in 0..10_000 {
+            if game.is_over() {
+                break;
+            }
+            game.step(FRAME, &[GameAction::HardDrop]);
+        }
+        game
+    }
+
+    #[test]
+    fn first_tetromino_comes_from_the_queue() {
+        let mut game = Game::new(GameSettings::default(), RandomizerKind::SevenBag, 1);
+        let next = game.next_tetrominos()[0].kind();
+        game.step(FRAME, &[]);
+        game.step(FRAME, &[]);
+        assert!(!game.is_over());
+
+        let mut recorder = Recorder::default();
+        game.board().render(&mut recorder, 0, 0);
+        assert!(recorder.blocks.iter().any(|&(block, _, _)| block == Block::Piece(next)));
+    }
+
+    #[test]
+    fn stacking_in_the_middle_tops_out() {
+        let game = play_until_over(7);
+        assert!(game.is_over() && !game.is_finished());
+        assert!(game.pieces() > 5 && game.pieces() < 40, "{} pieces", game.pieces());
+
+        let mut recorder = Recorder::default();
+        render_game(&game, &mut recorder);
+    }
+
+    #[test]
+    fn same_seed_plays_out_the_same() {
+        let (first, second) = (play_until_over(42), play_until_over(42));
+        assert_eq!(first.pieces(), second.pieces());
+        assert_eq!(first.scoring().score(), second.scoring().score());
+        assert_eq!(first.time(), second.time());
+    }
+
+    #[test]
+    fn time_stands_still_while_paused() {
+        let mut game = Game::new(GameSettings::default(), RandomizerKind::SevenBag, 3);
+        // The first step only sets the game up and starts the clock from zero
+        game.step(FRAME, &[]);
+        game.step(FRAME, &[]);
+        game.pause();
+        game.step(Duration::from_secs(10), &[GameAction::HardDrop]);
+        assert_eq!(game.time(), FRAME);
+        game.resume();
+        assert!(!game.is_paused());
+    }
+}
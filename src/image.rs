@@ -0,0 +1,57 @@
+use std::{fs, path::{Path, PathBuf}};
+
+use crate::bitmap::{decode_bitmap, Bitmap, BitmapError};
+
+const PNG_SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+
+/// Loads a BMP or a PNG file, told apart by their signature rather than the file name
+pub fn load_image(filename: &Path) -> Result<Bitmap, BitmapError> {
+    let data = fs::read(filename)?;
+    if data.starts_with(PNG_SIGNATURE) {
+        decode_png(&data)
+    } else {
+        decode_bitmap(&data)
+    }
+}
+
+/// `<dir>/<name>.png`, or `<dir>/<name>.bmp` when there is no PNG
+pub fn asset_path(dir: &Path, name: &str) -> PathBuf {
+    let png = dir.join(format!("{}.png", name));
+    if png.exists() { png } else { dir.join(format!("{}.bmp", name)) }
+}
+
+/// Decodes any PNG to the same ARGB pixels as bitmaps, palettes, grayscale and 16 bits channels
+/// are expanded to 8 bits RGBA and transparency chunks become alpha
+#[cfg(feature = "png")]
+pub fn decode_png(data: &[u8]) -> Result<Bitmap, BitmapError> {
+    use png::{ColorType, Decoder, Transformations};
+
+    let mut decoder = Decoder::new(data);
+    decoder.set_transformations(Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut buffer)?;
+
+    let argb = |r: u8, g: u8, b: u8, a: u8| (a as u32) << 24 | (r as u32) << 16 | (g as u32) << 8 | b as u32;
+    let channels = frame.color_type.samples();
+    let mut pixels = Vec::with_capacity(frame.width as usize * frame.height as usize);
+    for row in buffer.chunks_exact(frame.line_size).take(frame.height as usize) {
+        for p in row.chunks_exact(channels).take(frame.width as usize) {
+            pixels.push(match frame.color_type {
+                ColorType::Grayscale => argb(p[0], p[0], p[0], 0xFF),
+                ColorType::GrayscaleAlpha => argb(p[0], p[0], p[0], p[1]),
+                ColorType::Rgb => argb(p[0], p[1], p[2], 0xFF),
+                ColorType::Rgba => argb(p[0], p[1], p[2], p[3]),
+                // Expanded away by normalize_to_color8
+                ColorType::Indexed => unreachable!("PNG palette was not expanded"),
+            });
+        }
+    }
+
+    Ok(Bitmap { width: frame.width, height: frame.height, pixels })
+}
+
+#[cfg(not(feature = "png"))]
+pub fn decode_png(_data: &[u8]) -> Result<Bitmap, BitmapError> {
+    Err(BitmapError::PngNotSupported)
+}
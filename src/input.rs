@@ -0,0 +1,303 @@
+use std::{io::{Error, ErrorKind}, time::Duration};
+
+use minifb::Key;
+
+use crate::{action::GameAction, config::Config};
+
+// With an ARR of 0 the tetromino is shifted this many times a frame, enough to reach any wall
+const INSTANT_SHIFT_MOVES: u32 = 64;
+
+/// Which keys trigger which action, several keys can share an action
+pub struct Bindings {
+    bindings: Vec<(Key, GameAction)>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Self {
+            bindings: vec![
+                (Key::Left, GameAction::MoveLeft),
+                (Key::Right, GameAction::MoveRight),
+                (Key::Up, GameAction::RotateClockwise),
+                (Key::X, GameAction::RotateClockwise),
+                (Key::Z, GameAction::RotateCounterClockwise),
+                (Key::A, GameAction::Rotate180),
+                (Key::Down, GameAction::SoftDrop),
+                (Key::Space, GameAction::HardDrop),
+                (Key::C, GameAction::Hold),
+                (Key::LeftShift, GameAction::Hold),
+                (Key::Escape, GameAction::Pause),
+            ],
+        }
+    }
+}
+
+impl Bindings {
+    /// Starts from the default bindings, actions listed in the [controls] section replace their keys
+    pub fn from_config(config: &Config) -> Result<Self, Error> {
+        let mut bindings = Self::default();
+        let Some(controls) = config.section("controls") else { return Ok(bindings) };
+
+        for (name, value) in controls {
+            let action = GameAction::from_name(name)
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("Unknown action {} in [controls]", name)))?;
+
+            bindings.bindings.retain(|&(_, bound)| bound != action);
+            for key_name in value.as_array() {
+                let key = key_name.as_str()
+                    .and_then(key_from_name)
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("Unknown key {:?} for {}", key_name, name)))?;
+                bindings.bindings.push((key, action));
+            }
+        }
+
+        Ok(bindings)
+    }
+
+    fn is_held(&self, action: GameAction, held_keys: &[Key]) -> bool {
+        self.bindings.iter().any(|&(key, bound)| bound == action && held_keys.contains(&key))
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct InputSettings {
+    // Delayed Auto Shift, how long a direction is held before it starts repeating
+    pub das: Duration,
+    // Auto Repeat Rate, time between two repeated moves, 0 moves straight to the wall
+    pub arr: Duration,
+}
+
+impl Default for InputSettings {
+    fn default() -> Self {
+        Self {
+            das: Duration::from_millis(167),
+            arr: Duration::from_millis(33),
+        }
+    }
+}
+
+struct Shift {
+    action: GameAction,
+    pressed_at: Duration,
+    // Repeated moves already sent since DAS ran out
+    repeats: u32,
+}
+
+/// Turns the keys held on each frame into game actions, timing auto-repeat itself
+/// instead of relying on the OS key repeat. Timers keep running while no tetromino
+/// is in play, so DAS charges during the entry delay.
+pub struct Input {
+    settings: InputSettings,
+    bindings: Bindings,
+    held_actions: Vec<GameAction>,
+    shift: Option<Shift>,
+    // Time elapsed since the input started, adding up the frame durations
+    time: Duration,
+}
+
+impl Input {
+    pub fn new(settings: InputSettings, bindings: Bindings) -> Self {
+        Self {
+            settings,
+            bindings,
+            held_actions: Vec::new(),
+            shift: None,
+            time: Duration::ZERO,
+        }
+    }
+
+    pub fn settings_mut(&mut self) -> &mut InputSettings {
+        &mut self.settings
+    }
+
+    pub fn update(&mut self, held_keys: &[Key], dt: Duration) -> Vec<GameAction> {
+        self.time += dt;
+        let now = self.time;
+        let held_actions: Vec<GameAction> = GameAction::ALL.into_iter()
+            .filter(|&action| self.bindings.is_held(action, held_keys))
+            .collect();
+        let pressed = |action: GameAction| held_actions.contains(&action) && !self.held_actions.contains(&action);
+
+        let mut actions = Vec::new();
+
+        for action in [GameAction::MoveLeft, GameAction::MoveRight] {
+            if pressed(action) {
+                self.shift = Some(Shift { action, pressed_at: now, repeats: 0 });
+                actions.push(action);
+            }
+        }
+
+        // Releasing the newest direction falls back to the other one if it is still held
+        if let Some(shift) = &self.shift && !held_actions.contains(&shift.action) {
+            self.shift = [GameAction::MoveLeft, GameAction::MoveRight].into_iter()
+                .find(|action| held_actions.contains(action))
+                .map(|action| Shift { action, pressed_at: now, repeats: 0 });
+        }
+
+        if let Some(shift) = &mut self.shift {
+            let held_for = now - shift.pressed_at;
+            if held_for >= self.settings.das {
+                if self.settings.arr.is_zero() {
+                    actions.extend(std::iter::repeat_n(shift.action, INSTANT_SHIFT_MOVES as usize));
+                } else {
+                    let due = ((held_for - self.settings.das).as_nanos() / self.settings.arr.as_nanos()) as u32 + 1;
+                    actions.extend(std::iter::repeat_n(shift.action, due.saturating_sub(shift.repeats) as usize));
+                    shift.repeats = shift.repeats.max(due);
+                }
+            }
+        }
+
+        for action in [GameAction::RotateClockwise, GameAction::RotateCounterClockwise, GameAction::Rotate180, GameAction::HardDrop, GameAction::Hold, GameAction::Pause] {
+            if pressed(action) {
+                actions.push(action);
+            }
+        }
+
+        if held_actions.contains(&GameAction::SoftDrop) {
+            actions.push(GameAction::SoftDrop);
+        }
+
+        self.held_actions = held_actions;
+        actions
+    }
+}
+
+/// Maps the key names used in the config file, which are the minifb key names, to keys
+fn key_from_name(name: &str) -> Option<Key> {
+    const LETTERS: [Key; 26] = [
+        Key::A, Key::B, Key::C, Key::D, Key::E, Key::F, Key::G, Key::H, Key::I, Key::J, Key::K, Key::L, Key::M,
+        Key::N, Key::O, Key::P, Key::Q, Key::R, Key::S, Key::T, Key::U, Key::V, Key::W, Key::X, Key::Y, Key::Z,
+    ];
+    const DIGITS: [Key; 10] = [Key::Key0, Key::Key1, Key::Key2, Key::Key3, Key::Key4, Key::Key5, Key::Key6, Key::Key7, Key::Key8, Key::Key9];
+    const NUMPAD: [Key; 10] = [
+        Key::NumPad0, Key::NumPad1, Key::NumPad2, Key::NumPad3, Key::NumPad4,
+        Key::NumPad5, Key::NumPad6, Key::NumPad7, Key::NumPad8, Key::NumPad9,
+    ];
+
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        if c.is_ascii_uppercase() {
+            return Some(LETTERS[(c as u8 - b'A') as usize]);
+        }
+        if c.is_ascii_digit() {
+            return Some(DIGITS[(c as u8 - b'0') as usize]);
+        }
+    }
+
+    if let Some(digit) = name.strip_prefix("NumPad").and_then(|digit| digit.parse::<usize>().ok()) {
+        return NUMPAD.get(digit).copied();
+    }
+
+    let key = match name {
+        "Left" => Key::Left,
+        "Right" => Key::Right,
+        "Up" => Key::Up,
+        "Down" => Key::Down,
+        "Space" => Key::Space,
+        "Enter" => Key::Enter,
+        "Escape" => Key::Escape,
+        "Tab" => Key::Tab,
+        "Backspace" => Key::Backspace,
+        "LeftShift" => Key::LeftShift,
+        "RightShift" => Key::RightShift,
+        "LeftCtrl" => Key::LeftCtrl,
+        "RightCtrl" => Key::RightCtrl,
+        "LeftAlt" => Key::LeftAlt,
+        "RightAlt" => Key::RightAlt,
+        "Comma" => Key::Comma,
+        "Period" => Key::Period,
+        "Slash" => Key::Slash,
+        "Semicolon" => Key::Semicolon,
+        "Apostrophe" => Key::Apostrophe,
+        "Minus" => Key::Minus,
+        "Equal" => Key::Equal,
+        _ => return None,
+    };
+
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEFT: GameAction = GameAction::MoveLeft;
+    const RIGHT: GameAction = GameAction::MoveRight;
+
+    fn input(das: u64, arr: u64) -> Input {
+        let settings = InputSettings { das: Duration::from_millis(das), arr: Duration::from_millis(arr) };
+        Input::new(settings, Bindings::default())
+    }
+
+    fn update(input: &mut Input, held_keys: &[Key], dt: u64) -> Vec<GameAction> {
+        input.update(held_keys, Duration::from_millis(dt))
+    }
+
+    #[test]
+    fn tap_moves_once() {
+        let mut input = input(100, 20);
+        assert_eq!(update(&mut input, &[Key::Left], 16), [LEFT]);
+        assert_eq!(update(&mut input, &[Key::Left], 83), []);
+        assert_eq!(update(&mut input, &[], 16), []);
+        assert_eq!(update(&mut input, &[Key::Left], 16), [LEFT]);
+    }
+
+    #[test]
+    fn repeats_start_once_das_ran_out() {
+        let mut input = input(100, 20);
+        assert_eq!(update(&mut input, &[Key::Left], 0), [LEFT]);
+        assert_eq!(update(&mut input, &[Key::Left], 99), []);
+        assert_eq!(update(&mut input, &[Key::Left], 1), [LEFT]);
+        assert_eq!(update(&mut input, &[Key::Left], 19), []);
+        // A long frame sends every repeat that came due during it
+        assert_eq!(update(&mut input, &[Key::Left], 41), [LEFT; 3]);
+    }
+
+    #[test]
+    fn das_and_repeats_in_a_single_frame() {
+        let mut input = input(100, 20);
+        update(&mut input, &[Key::Right], 0);
+        assert_eq!(update(&mut input, &[Key::Right], 200), [RIGHT; 6]);
+    }
+
+    #[test]
+    fn zero_arr_shifts_to_the_wall() {
+        let mut input = input(100, 0);
+        assert_eq!(update(&mut input, &[Key::Right], 0), [RIGHT]);
+        assert_eq!(update(&mut input, &[Key::Right], 50), []);
+        assert_eq!(update(&mut input, &[Key::Right], 50), vec![RIGHT; INSTANT_SHIFT_MOVES as usize]);
+        assert_eq!(update(&mut input, &[Key::Right], 16).len(), INSTANT_SHIFT_MOVES as usize);
+    }
+
+    #[test]
+    fn newest_direction_wins_and_release_falls_back() {
+        let mut input = input(100, 20);
+        update(&mut input, &[Key::Left], 0);
+        assert_eq!(update(&mut input, &[Key::Left, Key::Right], 50), [RIGHT]);
+        assert_eq!(update(&mut input, &[Key::Left, Key::Right], 100), [RIGHT]);
+
+        // Left takes over again with its own DAS
+        assert_eq!(update(&mut input, &[Key::Left], 16), []);
+        assert_eq!(update(&mut input, &[Key::Left], 99), []);
+        assert_eq!(update(&mut input, &[Key::Left], 1), [LEFT]);
+    }
+
+    #[test]
+    fn releasing_both_stops_shifting() {
+        let mut input = input(100, 20);
+        update(&mut input, &[Key::Left], 0);
+        update(&mut input, &[Key::Left], 120);
+        assert_eq!(update(&mut input, &[], 100), []);
+        assert!(input.shift.is_none());
+    }
+
+    #[test]
+    fn other_actions_fire_on_press_and_soft_drop_while_held() {
+        let mut input = input(100, 20);
+        let held = [Key::Up, Key::Down, Key::Space];
+        assert_eq!(update(&mut input, &held, 16), [GameAction::RotateClockwise, GameAction::HardDrop, GameAction::SoftDrop]);
+        assert_eq!(update(&mut input, &held, 16), [GameAction::SoftDrop]);
+        // Two keys bound to the same action count as a single press
+        assert_eq!(update(&mut input, &[Key::C, Key::LeftShift], 16), [GameAction::Hold]);
+    }
+}
@@ -0,0 +1,47 @@
+const LINES_PER_LEVEL: u32 = 10;
+// Gravity is expressed in G, the number of rows a tetromino falls per frame at 60 frames per second
+const FRAMES_PER_SECOND: f32 = 60.0;
+const MAX_GRAVITY: f32 = 20.0;
+const MAX_GRAVITY_LEVEL: u32 = 20;
+
+pub struct Level {
+    start_level: u32,
+    lines: u32,
+}
+
+impl Level {
+    pub fn new(start_level: u32) -> Self {
+        assert!(start_level > 0, "Levels start at 1");
+
+        Self {
+            start_level,
+            lines: 0,
+        }
+    }
+
+    pub fn level(&self) -> u32 {
+        self.start_level + self.lines / LINES_PER_LEVEL
+    }
+
+    pub fn lines(&self) -> u32 {
+        self.lines
+    }
+
+    pub fn add_lines(&mut self, lines: u32) {
+        self.lines += lines;
+    }
+
+    /// Rows per second a tetromino falls at the current level, following the guideline
+    /// curve where a row takes (0.8 - (level - 1) * 0.007)^(level - 1) seconds.
+    /// Levels past the curve fall at 20G, straight to the bottom of the field.
+    pub fn gravity(&self) -> f32 {
+        let level = self.level();
+        if level >= MAX_GRAVITY_LEVEL {
+            return MAX_GRAVITY * FRAMES_PER_SECOND;
+        }
+
+        let exponent = (level - 1) as f32;
+        let seconds_per_row = (0.8 - exponent * 0.007).powf(exponent);
+        (1.0 / seconds_per_row).min(MAX_GRAVITY * FRAMES_PER_SECOND)
+    }
+}
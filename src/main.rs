@@ -1,41 +1,133 @@
+mod action;
 mod bitmap;
+mod clock;
+mod config;
 mod display;
 mod board;
+mod game;
+mod image;
+mod input;
+mod level;
+mod menu;
+mod mode;
+mod pause;
+mod randomizer;
+mod records;
+mod renderer;
+mod replay;
+mod scoring;
 mod tetromino;
+mod theme;
+mod title;
+mod view;
 
-use std::usize;
+use std::time::Duration;
 
-use board::Board;
+use action::GameAction;
+use clock::SystemClock;
 use display::Display;
-use minifb::{Key, Window, WindowOptions};
-use tetromino::Tetromino;
+use config::Config;
+use board::{FieldSize, MAX_FIELD_SIZE, MIN_FIELD_SIZE};
+use game::{Game, GameSettings};
+use mode::max_dig_rows;
+use input::{Bindings, Input, InputSettings};
+use menu::MenuInput;
+use minifb::{Key, KeyRepeat, Window, WindowOptions};
+use pause::{PauseChoice, PauseMenu};
+use randomizer::RandomizerKind;
+use records::Records;
+use renderer::Renderer;
+use replay::Replay;
+use tetromino::MAX_PREVIEW_COUNT;
+use theme::{theme_names, Theme, DEFAULT_THEME};
+use title::{ModeSelect, ModeSelectChoice};
 
 const WIDTH: usize = 1024;
 const HEIGHT: usize = 768;
 const PIXEL_SIZE: usize = 16;
-
-struct GameState {
-    display: Display,
-    state: State,
-    next_tetromino: Option<Tetromino>,
-}
-
-#[derive(PartialEq, Debug)]
-enum State {
-    NewGame,
-    DropBlock,
-    Playing,
-    NewTetrominoNeeded,
-    UpdateScore(i32),
-}
+const CONFIG_FILE: &str = "./config.toml";
+const REPLAY_FILE: &str = "./last.replay";
+const RECORDS_FILE: &str = "./records.txt";
 
 fn main() {
-    let mut board = Board::new(1, 5);
-    let mut gs = GameState {
-        display: Display::new(WIDTH, HEIGHT, PIXEL_SIZE, vec![0; WIDTH * HEIGHT]),
-        state: State::NewGame,
-        next_tetromino: None,
+    let args: Vec<String> = std::env::args().collect();
+    let randomizer = match arg_value(&args, "--randomizer") {
+        Some(name) => RandomizerKind::from_name(name).unwrap_or_else(|| panic!("Unknown randomizer {}, expected bag, nes or uniform", name)),
+        None => RandomizerKind::SevenBag,
     };
+    // Without a seed every game gets a new random one
+    let seed: Option<u64> = arg_value(&args, "--seed")
+        .map(|seed| seed.parse().unwrap_or_else(|_| panic!("Seed {} should be a number", seed)));
+
+    let mut settings = GameSettings::default();
+    if let Some(count) = arg_value(&args, "--preview") {
+        settings.preview_count = count.parse().ok()
+            .filter(|count| (1..=MAX_PREVIEW_COUNT).contains(count))
+            .unwrap_or_else(|| panic!("Preview count {} should be between 1 and {}", count, MAX_PREVIEW_COUNT));
+    }
+    if let Some(delay) = arg_value(&args, "--lock-delay") {
+        let millis = delay.parse().unwrap_or_else(|_| panic!("Lock delay {} should be a number of milliseconds", delay));
+        settings.lock_delay.delay = Duration::from_millis(millis);
+    }
+    if let Some(resets) = arg_value(&args, "--lock-resets") {
+        settings.lock_delay.max_resets = resets.parse().unwrap_or_else(|_| panic!("Lock resets {} should be a number", resets));
+    }
+    if let Some(factor) = arg_value(&args, "--soft-drop-factor") {
+        settings.soft_drop_factor = factor.parse().ok()
+            .filter(|factor: &f32| factor.is_finite() && *factor > 0.0)
+            .unwrap_or_else(|| panic!("Soft drop factor {} should be a number above 0", factor));
+    }
+    if let Some(size) = arg_value(&args, "--field") {
+        settings.field_size = parse_field_size(size).unwrap_or_else(|| panic!(
+            "Field size {} should be WIDTHxHEIGHT, between {}x{} and {}x{}",
+            size, MIN_FIELD_SIZE.width, MIN_FIELD_SIZE.height, MAX_FIELD_SIZE.width, MAX_FIELD_SIZE.height,
+        ));
+    }
+    if let Some(lines) = arg_value(&args, "--sprint-lines") {
+        settings.sprint_lines = lines.parse().ok()
+            .filter(|&lines| lines > 0)
+            .unwrap_or_else(|| panic!("Sprint lines {} should be a number above 0", lines));
+    }
+    if let Some(seconds) = arg_value(&args, "--ultra-time") {
+        settings.ultra_time = seconds.parse().ok()
+            .filter(|&seconds| seconds > 0)
+            .map(Duration::from_secs)
+            .unwrap_or_else(|| panic!("Ultra time {} should be a number of seconds above 0", seconds));
+    }
+    if let Some(rows) = arg_value(&args, "--dig-rows") {
+        settings.dig_rows = rows.parse().ok()
+            .filter(|rows| (1..=max_dig_rows(settings.field_size)).contains(rows))
+            .unwrap_or_else(|| panic!("Dig rows {} should be between 1 and {} on this field", rows, max_dig_rows(settings.field_size)));
+    } else {
+        settings.dig_rows = settings.dig_rows.min(max_dig_rows(settings.field_size));
+    }
+    if let Some(seconds) = arg_value(&args, "--dig-interval") {
+        let seconds = seconds.parse().ok()
+            .filter(|&seconds| seconds > 0)
+            .unwrap_or_else(|| panic!("Dig interval {} should be a number of seconds above 0", seconds));
+        settings.dig_interval = Some(Duration::from_secs(seconds));
+    }
+    if let Some(delay) = arg_value(&args, "--entry-delay") {
+        let millis = delay.parse().unwrap_or_else(|_| panic!("Entry delay {} should be a number of milliseconds", delay));
+        settings.entry_delay = Duration::from_millis(millis);
+    }
+
+    let mut input_settings = InputSettings::default();
+    if let Some(das) = arg_value(&args, "--das") {
+        let millis = das.parse().unwrap_or_else(|_| panic!("DAS {} should be a number of milliseconds", das));
+        input_settings.das = Duration::from_millis(millis);
+    }
+    if let Some(arr) = arg_value(&args, "--arr") {
+        let millis = arr.parse().unwrap_or_else(|_| panic!("ARR {} should be a number of milliseconds", arr));
+        input_settings.arr = Duration::from_millis(millis);
+    }
+
+    let config = Config::load(CONFIG_FILE).unwrap_or_else(|error| panic!("{}", error));
+    let bindings = Bindings::from_config(&config).unwrap_or_else(|error| panic!("{}", error));
+    let mut input = Input::new(input_settings, bindings);
+    let theme = Theme::load(arg_value(&args, "--theme").unwrap_or(DEFAULT_THEME)).unwrap_or_else(|error| panic!("{}", error));
+    let mut display = Display::new(WIDTH, HEIGHT, PIXEL_SIZE, vec![0; WIDTH * HEIGHT], theme);
+    display.show_clipping = args.iter().any(|arg| arg == "--show-clipping");
 
     let mut window = Window::new(
         "Tetrust+",
@@ -48,50 +140,219 @@ fn main() {
     });
 
     window.set_target_fps(60);
-    let mut score = 0;
 
-    while window.is_open() && !window.is_key_down(Key::Escape) {
-        gs.display.clear_buffer();
-        gs.display.draw_text(28, 1, "tetris!".to_string());
+    if let Some(filename) = arg_value(&args, "--replay") {
+        let replay = Replay::load(filename).unwrap_or_else(|error| panic!("{}", error));
+        watch_replay(&replay, &mut window, &mut display, &mut input);
+    } else {
+        let replay_file = arg_value(&args, "--record").unwrap_or(REPLAY_FILE);
+        let setup = GameSetup { randomizer, seed, settings };
+        run_menus(setup, replay_file, &mut window, &mut display, &mut input);
+    }
+}
+
+/// How new games are started, each game gets a random seed when none is given
+#[derive(Copy, Clone)]
+struct GameSetup {
+    randomizer: RandomizerKind,
+    seed: Option<u64>,
+    settings: GameSettings,
+}
 
-        match gs.state {
-            State::NewGame => gs.state = State::NewTetrominoNeeded,
-            State::NewTetrominoNeeded => {
-                board.place_new_tetromino(&mut gs);
-                gs.state = State::Playing;
+impl GameSetup {
+    fn start(&self) -> (Game, Replay) {
+        let replay = Replay::new(self.randomizer, self.seed.unwrap_or_else(rand::random), self.settings);
+        (Game::new(self.settings, self.randomizer, replay.seed), replay)
+    }
+}
+
+/// Shows the title screen and the mode select, starting games from there until the player leaves
+fn run_menus(setup: GameSetup, replay_file: &str, window: &mut Window, display: &mut Display, input: &mut Input) {
+    let mut records = Records::load(RECORDS_FILE).unwrap_or_else(|error| panic!("{}", error));
+    let mut mode_select = ModeSelect::new(setup.settings, setup.randomizer);
+    let mut on_title = true;
+
+    while window.is_open() {
+        for menu_input in menu_inputs(window) {
+            if on_title {
+                match menu_input {
+                    MenuInput::Select => on_title = false,
+                    MenuInput::Back => return,
+                    _ => {},
+                }
+                continue;
             }
-            State::UpdateScore(line_erased) => {
-                gs.state = State::NewTetrominoNeeded;
-                score += match line_erased
-                {
-                    1 => 40,
-                    2 => 100,
-                    3 => 300,
-                    _ => 1200
-                };
+
+            match mode_select.handle(menu_input) {
+                Some(ModeSelectChoice::Start(mode)) => {
+                    let setup = GameSetup {
+                        randomizer: mode_select.randomizer(),
+                        settings: GameSettings { mode, ..*mode_select.settings() },
+                        ..setup
+                    };
+                    play(setup, replay_file, &mut records, window, display, input);
+                    break;
+                }
+                Some(ModeSelectChoice::Back) => on_title = true,
+                None => {},
             }
-            State::Playing => {
-                board.update(&mut gs, &window.get_keys_pressed(minifb::KeyRepeat::Yes));
+        }
+
+        display.clear_buffer();
+        if on_title {
+            view::render_title(display);
+        } else {
+            view::render_mode_select(&mode_select, &records, display);
+        }
+        present(window, display);
+    }
+}
+
+/// Runs games until the player quits to the mode select, each game is recorded and saved
+/// to `replay_file` once it ends
+fn play(setup: GameSetup, replay_file: &str, records: &mut Records, window: &mut Window, display: &mut Display, input: &mut Input) {
+    let (mut game, mut replay) = setup.start();
+    let mut clock = SystemClock::new();
+
+    let mut pause_menu: Option<PauseMenu> = None;
+
+    while window.is_open() {
+        let dt = clock.tick();
+
+        if let Some(menu) = &mut pause_menu {
+            let theme = menu.theme().to_string();
+            let choice = menu_inputs(window).into_iter()
+                .find_map(|menu_input| menu.handle(menu_input, input.settings_mut()));
+            if menu.theme() != theme {
+                match Theme::load(menu.theme()) {
+                    Ok(theme) => display.set_theme(theme),
+                    Err(error) => eprintln!("Could not load the theme: {}", error),
+                }
+            }
+            match choice {
+                Some(PauseChoice::Resume) => {
+                    game.resume();
+                    pause_menu = None;
+                }
+                Some(PauseChoice::Restart) => {
+                    save_replay(&replay, replay_file);
+                    (game, replay) = setup.start();
+                    pause_menu = None;
+                }
+                Some(PauseChoice::Quit) => break,
+                None => {},
+            }
+        } else {
+            let actions = input.update(&window.get_keys(), dt);
+            if game.is_over() {
+                // Once the game is over pausing goes back to the mode select
+                if actions.contains(&GameAction::Pause) {
+                    break;
+                }
+                if window.is_key_pressed(Key::Enter, KeyRepeat::No) {
+                    (game, replay) = setup.start();
+                }
+            } else if actions.contains(&GameAction::Pause) {
+                game.pause();
+                pause_menu = Some(PauseMenu::new(theme_names(), &display.theme().name));
+            } else {
+                let dt = replay.record(dt, &actions);
+                game.step(dt, &actions);
+                if game.is_over() {
+                    save_replay(&replay, replay_file);
+                    submit_result(&game, records);
+                }
             }
-            _ => {},
         }
 
-        board.render(&mut gs);
-        draw_next_tetromino(&mut gs);
-        draw_score(score, &mut gs);
+        display.clear_buffer();
+        view::render_game(&game, display);
+        if let Some(menu) = &pause_menu {
+            view::render_menu(menu.current(), display);
+        }
+        present(window, display);
+    }
 
-        window.update_with_buffer(&gs.display.buffer, WIDTH, HEIGHT).unwrap();
+    // Leaving in the middle of a game still keeps what was played
+    if !game.is_over() {
+        save_replay(&replay, replay_file);
     }
 }
 
-fn draw_next_tetromino(gs: &mut GameState) {
-    gs.display.draw_text(30, 8, "NEXT".to_string());
-    if let Some(tetromino) = &gs.next_tetromino {
-        tetromino.render(30, 9, &mut gs.display);
+/// Plays the recorded frames back at the pace they were recorded
+fn watch_replay(replay: &Replay, window: &mut Window, display: &mut Display, input: &mut Input) {
+    let mut game = Game::new(replay.settings, replay.randomizer, replay.seed);
+    let mut frames = replay.frames().iter();
+    let mut clock = SystemClock::new();
+    let mut time = Duration::ZERO;
+    let mut replay_time = Duration::ZERO;
+
+    while window.is_open() {
+        let dt = clock.tick();
+        time += dt;
+        if input.update(&window.get_keys(), dt).contains(&GameAction::Pause) {
+            break;
+        }
+
+        // Several recorded frames can come up during a slow frame, or none during a fast one
+        while replay_time <= time && let Some(frame) = frames.next() {
+            replay_time += frame.dt;
+            game.step(frame.dt, &frame.actions);
+        }
+
+        display.clear_buffer();
+        view::render_game(&game, display);
+        display.draw_text(38, 1, "REPLAY".to_string());
+        present(window, display);
+    }
+}
+
+fn menu_inputs(window: &Window) -> Vec<MenuInput> {
+    window.get_keys_pressed(KeyRepeat::Yes).into_iter()
+        .filter_map(|key| match key {
+            Key::Up => Some(MenuInput::Up),
+            Key::Down => Some(MenuInput::Down),
+            Key::Left => Some(MenuInput::Left),
+            Key::Right => Some(MenuInput::Right),
+            Key::Enter => Some(MenuInput::Select),
+            Key::Escape => Some(MenuInput::Back),
+            _ => None,
+        })
+        .collect()
+}
+
+fn submit_result(game: &Game, records: &mut Records) {
+    let settings = game.settings();
+    if let Some(result) = settings.mode.result(game) && records.submit(settings.mode, settings, result) && let Err(error) = records.save() {
+        eprintln!("Could not save the records to {}: {}", RECORDS_FILE, error);
+    }
+}
+
+fn save_replay(replay: &Replay, filename: &str) {
+    if let Err(error) = replay.save(filename) {
+        eprintln!("Could not save the replay to {}: {}", filename, error);
     }
 }
 
-fn draw_score(score: i32, gs: &mut GameState) {
-    gs.display.draw_text(30, 14, "SCORE".to_string());
-    gs.display.draw_text(30, 15, score.to_string());
+fn parse_field_size(size: &str) -> Option<FieldSize> {
+    let (width, height) = size.split_once('x')?;
+    let size = FieldSize { width: width.parse().ok()?, height: height.parse().ok()? };
+    let fits = (MIN_FIELD_SIZE.width..=MAX_FIELD_SIZE.width).contains(&size.width)
+        && (MIN_FIELD_SIZE.height..=MAX_FIELD_SIZE.height).contains(&size.height);
+
+    fits.then_some(size)
+}
+
+// Shows the frame drawn in the display
+fn present(window: &mut Window, display: &mut Display) {
+    display.draw_debug_overlay();
+    display.reload_theme_if_changed();
+    window.update_with_buffer(&display.buffer, display.width, display.height).unwrap();
+}
+
+fn arg_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|index| args.get(index + 1))
+        .map(|value| value.as_str())
 }
@@ -0,0 +1,72 @@
+use crate::renderer::{BlendMode, Renderer};
+
+// Added over the selected item
+const HIGHLIGHT_COLOR: u32 = 0x30FFFFFF;
+
+/// Keys understood by menus, the window maps its own keys to these
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MenuInput {
+    Up,
+    Down,
+    Left,
+    Right,
+    Select,
+    Back,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MenuEvent {
+    Selected(usize),
+    // Left or right on an item, -1 or 1
+    Changed(usize, i32),
+    Back,
+}
+
+/// A titled list of items navigated with up and down, the selected item is marked with >
+pub struct Menu {
+    title: String,
+    items: Vec<String>,
+    selected: usize,
+}
+
+impl Menu {
+    pub fn new(title: &str, items: Vec<String>) -> Self {
+        Self { title: title.to_string(), items, selected: 0 }
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    pub fn set_item(&mut self, index: usize, item: String) {
+        self.items[index] = item;
+    }
+
+    pub fn handle(&mut self, input: MenuInput) -> Option<MenuEvent> {
+        match input {
+            MenuInput::Up => {
+                self.selected = (self.selected + self.items.len() - 1) % self.items.len();
+                None
+            }
+            MenuInput::Down => {
+                self.selected = (self.selected + 1) % self.items.len();
+                None
+            }
+            MenuInput::Left => Some(MenuEvent::Changed(self.selected, -1)),
+            MenuInput::Right => Some(MenuEvent::Changed(self.selected, 1)),
+            MenuInput::Select => Some(MenuEvent::Selected(self.selected)),
+            MenuInput::Back => Some(MenuEvent::Back),
+        }
+    }
+
+    pub fn render(&self, renderer: &mut dyn Renderer, x: u32, y: u32) {
+        renderer.draw_text(x, y, self.title.clone());
+        for (i, item) in self.items.iter().enumerate() {
+            let marker = if i == self.selected { ">" } else { " " };
+            renderer.draw_text(x, y + 2 + i as u32, format!("{}{}", marker, item));
+            if i == self.selected {
+                renderer.fill_rect(HIGHLIGHT_COLOR, x, y + 2 + i as u32, item.len() as u32 + 1, 1, BlendMode::Additive);
+            }
+        }
+    }
+}
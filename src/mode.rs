@@ -0,0 +1,135 @@
+use std::time::Duration;
+
+use crate::{board::{FieldSize, SPAWN_ROWS}, game::{Game, GameSettings}};
+
+// Garbage a dig can start with, leaving room above it to play
+pub const MAX_DIG_ROWS: u32 = 15;
+
+/// Most garbage rows a Dig game can start with on a field, the top rows stay free for tetrominos to come in
+pub fn max_dig_rows(field_size: FieldSize) -> u32 {
+    MAX_DIG_ROWS.min(field_size.height.saturating_sub(SPAWN_ROWS) as u32)
+}
+
+/// Rules a game is played under and how its result counts towards personal bests
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mode {
+    Marathon,
+    Sprint,
+    Ultra,
+    Dig,
+}
+
+impl Mode {
+    pub const ALL: [Mode; 4] = [Mode::Marathon, Mode::Sprint, Mode::Ultra, Mode::Dig];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Marathon => "MARATHON",
+            Mode::Sprint => "SPRINT",
+            Mode::Ultra => "ULTRA",
+            Mode::Dig => "DIG",
+        }
+    }
+
+    pub fn description(self, settings: &GameSettings) -> String {
+        match self {
+            Mode::Marathon => "ENDLESS PLAY. THE SPEED RISES EVERY 10 LINES".to_string(),
+            Mode::Sprint => format!("CLEAR {} LINES AS FAST AS POSSIBLE", settings.sprint_lines),
+            Mode::Ultra => format!("SCORE AS MUCH AS POSSIBLE IN {} SECONDS", settings.ultra_time.as_secs()),
+            Mode::Dig => match settings.dig_interval {
+                Some(interval) => format!("DIG THROUGH {} GARBAGE ROWS. ONE MORE EVERY {} SECONDS", settings.dig_rows, interval.as_secs()),
+                None => format!("DIG THROUGH {} GARBAGE ROWS", settings.dig_rows),
+            },
+        }
+    }
+
+    /// Number identifying the mode in replay files
+    pub fn id(self) -> u8 {
+        match self {
+            Mode::Marathon => 0,
+            Mode::Sprint => 1,
+            Mode::Ultra => 2,
+            Mode::Dig => 3,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|mode| mode.id() == id)
+    }
+
+    /// Name the personal best is saved under, sprints to different line counts are kept apart
+    pub fn record_key(self, settings: &GameSettings) -> String {
+        match self {
+            Mode::Marathon => "marathon".to_string(),
+            Mode::Sprint => format!("sprint{}", settings.sprint_lines),
+            Mode::Ultra => format!("ultra{}", settings.ultra_time.as_secs()),
+            Mode::Dig => match settings.dig_interval {
+                Some(interval) => format!("dig{}-{}", settings.dig_rows, interval.as_secs()),
+                None => format!("dig{}", settings.dig_rows),
+            },
+        }
+    }
+
+    /// Whether the game reached the goal of the mode, checked each time a tetromino settles
+    pub fn is_finished(self, game: &Game) -> bool {
+        match self {
+            Mode::Marathon | Mode::Ultra => false,
+            Mode::Sprint => game.level().lines() >= game.settings().sprint_lines,
+            Mode::Dig => game.board().garbage_rows() == 0,
+        }
+    }
+
+    /// Garbage rows the field starts with
+    pub fn start_garbage(self, settings: &GameSettings) -> usize {
+        match self {
+            Mode::Dig => settings.dig_rows as usize,
+            _ => 0,
+        }
+    }
+
+    /// Time between two garbage rows rising during the game
+    pub fn garbage_interval(self, settings: &GameSettings) -> Option<Duration> {
+        match self {
+            Mode::Dig => settings.dig_interval,
+            _ => None,
+        }
+    }
+
+    /// Game time after which the game ends
+    pub fn time_limit(self, settings: &GameSettings) -> Option<Duration> {
+        match self {
+            Mode::Marathon | Mode::Sprint | Mode::Dig => None,
+            Mode::Ultra => Some(settings.ultra_time),
+        }
+    }
+
+    /// Result of a finished game to compare with personal bests, None when it doesn't count
+    pub fn result(self, game: &Game) -> Option<u64> {
+        match self {
+            Mode::Marathon | Mode::Ultra => Some(game.scoring().score() as u64),
+            // Sprint and dig times are kept in milliseconds
+            Mode::Sprint | Mode::Dig if game.is_finished() => Some(game.time().as_millis() as u64),
+            Mode::Sprint | Mode::Dig => None,
+        }
+    }
+
+    pub fn is_better(self, result: u64, best: u64) -> bool {
+        match self {
+            Mode::Marathon | Mode::Ultra => result > best,
+            Mode::Sprint | Mode::Dig => result < best,
+        }
+    }
+
+    pub fn format_result(self, result: u64) -> String {
+        match self {
+            Mode::Marathon | Mode::Ultra => result.to_string(),
+            Mode::Sprint | Mode::Dig => format_time(Duration::from_millis(result)),
+        }
+    }
+}
+
+/// Formats a time as minutes, seconds and milliseconds, like 1:23.456
+pub fn format_time(time: Duration) -> String {
+    let millis = time.as_millis();
+    format!("{}:{:02}.{:03}", millis / 60_000, millis / 1000 % 60, millis % 1000)
+}
@@ -0,0 +1,102 @@
+use std::time::Duration;
+
+use crate::{input::InputSettings, menu::{Menu, MenuEvent, MenuInput}};
+
+const RESUME_ITEM: usize = 0;
+const RESTART_ITEM: usize = 1;
+const SETTINGS_ITEM: usize = 2;
+const QUIT_ITEM: usize = 3;
+
+const DAS_ITEM: usize = 0;
+const ARR_ITEM: usize = 1;
+const THEME_ITEM: usize = 2;
+const BACK_ITEM: usize = 3;
+
+const DAS_STEP: Duration = Duration::from_millis(10);
+const ARR_STEP: Duration = Duration::from_millis(5);
+const MAX_DAS: Duration = Duration::from_millis(500);
+const MAX_ARR: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PauseChoice {
+    Resume,
+    Restart,
+    Quit,
+}
+
+/// The menu shown while the game is paused, with a settings page for DAS, ARR and the theme.
+/// Other settings change how a game plays out, so they stay as they were when it started.
+pub struct PauseMenu {
+    main: Menu,
+    settings: Option<Menu>,
+    themes: Vec<String>,
+    theme: usize,
+}
+
+impl PauseMenu {
+    pub fn new(themes: Vec<String>, current_theme: &str) -> Self {
+        let items = ["RESUME", "RESTART", "SETTINGS", "QUIT"].map(String::from).to_vec();
+        let theme = themes.iter().position(|name| name == current_theme).unwrap_or(0);
+        Self { main: Menu::new("PAUSED", items), settings: None, themes, theme }
+    }
+
+    /// The theme picked on the settings page
+    pub fn theme(&self) -> &str {
+        &self.themes[self.theme]
+    }
+
+    /// The menu currently on screen
+    pub fn current(&self) -> &Menu {
+        self.settings.as_ref().unwrap_or(&self.main)
+    }
+
+    pub fn handle(&mut self, input: MenuInput, input_settings: &mut InputSettings) -> Option<PauseChoice> {
+        if let Some(settings) = &mut self.settings {
+            match settings.handle(input) {
+                Some(MenuEvent::Changed(DAS_ITEM, direction)) => {
+                    input_settings.das = step(input_settings.das, DAS_STEP, direction, MAX_DAS);
+                }
+                Some(MenuEvent::Changed(ARR_ITEM, direction)) => {
+                    input_settings.arr = step(input_settings.arr, ARR_STEP, direction, MAX_ARR);
+                }
+                Some(MenuEvent::Changed(THEME_ITEM, direction)) => {
+                    self.theme = (self.theme as i32 + direction).rem_euclid(self.themes.len() as i32) as usize;
+                }
+                Some(MenuEvent::Selected(BACK_ITEM)) | Some(MenuEvent::Back) => self.settings = None,
+                _ => {},
+            }
+
+            if let Some(settings) = &mut self.settings {
+                update_settings_items(settings, input_settings, &self.themes[self.theme]);
+            }
+            return None;
+        }
+
+        match self.main.handle(input)? {
+            MenuEvent::Selected(RESUME_ITEM) | MenuEvent::Back => Some(PauseChoice::Resume),
+            MenuEvent::Selected(RESTART_ITEM) => Some(PauseChoice::Restart),
+            MenuEvent::Selected(SETTINGS_ITEM) => {
+                let mut settings = Menu::new("SETTINGS", vec![String::new(), String::new(), String::new(), "BACK".to_string()]);
+                update_settings_items(&mut settings, input_settings, self.theme());
+                self.settings = Some(settings);
+                None
+            }
+            MenuEvent::Selected(QUIT_ITEM) => Some(PauseChoice::Quit),
+            _ => None,
+        }
+    }
+}
+
+fn update_settings_items(settings: &mut Menu, input_settings: &InputSettings, theme: &str) {
+    settings.set_item(DAS_ITEM, format!("DAS {}", input_settings.das.as_millis()));
+    settings.set_item(ARR_ITEM, format!("ARR {}", input_settings.arr.as_millis()));
+    settings.set_item(THEME_ITEM, format!("THEME {}", theme));
+}
+
+fn step(value: Duration, step: Duration, direction: i32, max: Duration) -> Duration {
+    if direction < 0 {
+        value.saturating_sub(step)
+    } else {
+        (value + step).min(max)
+    }
+}
@@ -0,0 +1,118 @@
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::tetromino::TetrominoKind;
+
+pub trait Randomizer {
+    fn next_kind(&mut self) -> TetrominoKind;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RandomizerKind {
+    SevenBag,
+    Nes,
+    Uniform,
+}
+
+impl RandomizerKind {
+    pub const ALL: [RandomizerKind; 3] = [RandomizerKind::SevenBag, RandomizerKind::Nes, RandomizerKind::Uniform];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RandomizerKind::SevenBag => "bag",
+            RandomizerKind::Nes => "nes",
+            RandomizerKind::Uniform => "uniform",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+
+    /// Number identifying the randomizer in replay files
+    pub fn id(self) -> u8 {
+        match self {
+            RandomizerKind::SevenBag => 0,
+            RandomizerKind::Nes => 1,
+            RandomizerKind::Uniform => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.id() == id)
+    }
+
+    pub fn create(self, seed: u64) -> Box<dyn Randomizer> {
+        let rng = StdRng::seed_from_u64(seed);
+        match self {
+            RandomizerKind::SevenBag => Box::new(SevenBag::new(rng)),
+            RandomizerKind::Nes => Box::new(Nes::new(rng)),
+            RandomizerKind::Uniform => Box::new(Uniform::new(rng)),
+        }
+    }
+}
+
+/// Deals all seven tetrominos in a shuffled order before refilling the bag
+pub struct SevenBag {
+    rng: StdRng,
+    bag: Vec<TetrominoKind>,
+}
+
+impl SevenBag {
+    pub fn new(rng: StdRng) -> Self {
+        Self { rng, bag: Vec::with_capacity(TetrominoKind::ALL.len()) }
+    }
+}
+
+impl Randomizer for SevenBag {
+    fn next_kind(&mut self) -> TetrominoKind {
+        if self.bag.is_empty() {
+            self.bag.extend_from_slice(&TetrominoKind::ALL);
+            self.bag.shuffle(&mut self.rng);
+        }
+
+        self.bag.pop().unwrap_or(TetrominoKind::I)
+    }
+}
+
+/// Classic NES generator: rolls an eighth "reroll" value, and rolls once more
+/// on it or when the roll repeats the previous tetromino
+pub struct Nes {
+    rng: StdRng,
+    last: Option<TetrominoKind>,
+}
+
+impl Nes {
+    pub fn new(rng: StdRng) -> Self {
+        Self { rng, last: None }
+    }
+}
+
+impl Randomizer for Nes {
+    fn next_kind(&mut self) -> TetrominoKind {
+        let count = TetrominoKind::ALL.len();
+        let roll = self.rng.gen_range(0..=count);
+        let kind = match TetrominoKind::ALL.get(roll) {
+            Some(&kind) if Some(kind) != self.last => kind,
+            _ => TetrominoKind::ALL[self.rng.gen_range(0..count)],
+        };
+
+        self.last = Some(kind);
+        kind
+    }
+}
+
+pub struct Uniform {
+    rng: StdRng,
+}
+
+impl Uniform {
+    pub fn new(rng: StdRng) -> Self {
+        Self { rng }
+    }
+}
+
+impl Randomizer for Uniform {
+    fn next_kind(&mut self) -> TetrominoKind {
+        TetrominoKind::ALL[self.rng.gen_range(0..TetrominoKind::ALL.len())]
+    }
+}
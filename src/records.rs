@@ -0,0 +1,50 @@
+use std::{collections::HashMap, fs, io::{Error, ErrorKind}};
+
+use crate::{game::GameSettings, mode::Mode};
+
+/// Personal best of each mode, saved as one `mode result` line per mode, like `sprint40 93451`
+pub struct Records {
+    filename: String,
+    bests: HashMap<String, u64>,
+}
+
+impl Records {
+    /// Loads the records, a missing file means no records yet
+    pub fn load(filename: &str) -> Result<Self, Error> {
+        let mut records = Self { filename: filename.to_string(), bests: HashMap::new() };
+        let text = match fs::read_to_string(filename) {
+            Ok(text) => text,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(records),
+            Err(error) => return Err(error),
+        };
+
+        for (index, line) in text.lines().enumerate() {
+            let invalid = || Error::new(ErrorKind::InvalidData, format!("{}: line {} should be a mode and a number", filename, index + 1));
+            let Some((mode, result)) = line.split_once(' ') else { return Err(invalid()) };
+            let result = result.trim().parse().map_err(|_| invalid())?;
+            records.bests.insert(mode.to_string(), result);
+        }
+
+        Ok(records)
+    }
+
+    pub fn best(&self, mode: Mode, settings: &GameSettings) -> Option<u64> {
+        self.bests.get(&mode.record_key(settings)).copied()
+    }
+
+    /// Keeps the result when it beats the personal best of the mode, returns whether it did
+    pub fn submit(&mut self, mode: Mode, settings: &GameSettings, result: u64) -> bool {
+        if self.best(mode, settings).is_some_and(|best| !mode.is_better(result, best)) {
+            return false;
+        }
+
+        self.bests.insert(mode.record_key(settings), result);
+        true
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        let mut lines: Vec<String> = self.bests.iter().map(|(mode, result)| format!("{} {}\n", mode, result)).collect();
+        lines.sort();
+        fs::write(&self.filename, lines.concat())
+    }
+}
@@ -0,0 +1,52 @@
+use crate::tetromino::TetrominoKind;
+
+/// Drawing surface for the game, positions are in blocks rather than pixels
+pub trait Renderer {
+    fn draw_block(&mut self, block: Block, x: u32, y: u32);
+    fn draw_text(&mut self, x: u32, y: u32, text: String);
+    fn fill_rect(&mut self, color: u32, x: u32, y: u32, width: u32, height: u32, mode: BlendMode);
+}
+
+/// How a drawn pixel is combined with the pixel already in the buffer, every mode but opaque
+/// is weighted by the alpha of the drawn pixel
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BlendMode {
+    // Replaces the pixel, alpha included
+    Opaque,
+    Alpha,
+    // Adds the colors, for glows and flashes
+    Additive,
+    // Multiplies the colors, for shadows and tints
+    Multiply,
+}
+
+/// What a block on screen is, the theme of the renderer decides how each one looks
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Block {
+    // A cell of the field with nothing in it
+    Empty,
+    Piece(TetrominoKind),
+    // A piece on the ground, darker with each step of its lock delay, from 1 to LOCK_DIM_STEPS
+    Locking(TetrominoKind, u8),
+    Ghost(TetrominoKind),
+    Garbage,
+    Border,
+}
+
+/// Renderer for tests without a window, keeping the blocks drawn and ignoring the rest
+#[cfg(test)]
+#[derive(Default)]
+pub struct Recorder {
+    pub blocks: Vec<(Block, u32, u32)>,
+}
+
+#[cfg(test)]
+impl Renderer for Recorder {
+    fn draw_block(&mut self, block: Block, x: u32, y: u32) {
+        self.blocks.push((block, x, y));
+    }
+
+    fn draw_text(&mut self, _x: u32, _y: u32, _text: String) {}
+
+    fn fill_rect(&mut self, _color: u32, _x: u32, _y: u32, _width: u32, _height: u32, _mode: BlendMode) {}
+}
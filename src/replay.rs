@@ -0,0 +1,259 @@
+use std::{fs::File, io::{BufReader, BufWriter, Error, ErrorKind, Read, Write}, time::Duration};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::{action::GameAction, board::{FieldSize, LockDelay, MAX_FIELD_SIZE, MIN_FIELD_SIZE}, game::GameSettings, mode::{max_dig_rows, Mode}, randomizer::RandomizerKind, tetromino::MAX_PREVIEW_COUNT, title::MAX_START_LEVEL};
+
+const MAGIC: &[u8; 4] = b"TRPL";
+const VERSION: u8 = 7;
+
+/// Actions the game received on one frame and the time that went by before it
+pub struct Frame {
+    pub dt: Duration,
+    pub actions: Vec<GameAction>,
+}
+
+/// Everything needed to play a game again: the settings and seed it started with, and its frames.
+///
+/// The file starts with a header holding the settings, then each frame is stored as its duration
+/// in microseconds followed by the number of actions as a u16 and one byte per action.
+pub struct Replay {
+    pub randomizer: RandomizerKind,
+    pub seed: u64,
+    pub settings: GameSettings,
+    frames: Vec<Frame>,
+}
+
+impl Replay {
+    pub fn new(randomizer: RandomizerKind, seed: u64, settings: GameSettings) -> Self {
+        Self { randomizer, seed, settings, frames: Vec::new() }
+    }
+
+    /// Frame durations are stored in whole microseconds, the game should be stepped
+    /// with the returned duration so playback sees exactly the same times
+    pub fn record(&mut self, dt: Duration, actions: &[GameAction]) -> Duration {
+        let dt = Duration::from_micros(dt.as_micros().min(u32::MAX as u128) as u64);
+        self.frames.push(Frame { dt, actions: actions.to_vec() });
+        dt
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn save(&self, filename: &str) -> Result<(), Error> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        writer.write_all(MAGIC)?;
+        writer.write_u8(VERSION)?;
+        writer.write_u8(self.randomizer.id())?;
+        writer.write_u64::<LittleEndian>(self.seed)?;
+        writer.write_u8(self.settings.mode.id())?;
+        writer.write_u32::<LittleEndian>(self.settings.start_level)?;
+        writer.write_u16::<LittleEndian>(self.settings.field_size.width as u16)?;
+        writer.write_u16::<LittleEndian>(self.settings.field_size.height as u16)?;
+        writer.write_u32::<LittleEndian>(self.settings.sprint_lines)?;
+        writer.write_u64::<LittleEndian>(self.settings.ultra_time.as_micros() as u64)?;
+        writer.write_u32::<LittleEndian>(self.settings.dig_rows)?;
+        // No interval is stored as 0
+        writer.write_u64::<LittleEndian>(self.settings.dig_interval.map_or(0, |interval| interval.as_micros() as u64))?;
+        writer.write_u8(self.settings.preview_count as u8)?;
+        writer.write_u64::<LittleEndian>(self.settings.lock_delay.delay.as_micros() as u64)?;
+        writer.write_u32::<LittleEndian>(self.settings.lock_delay.max_resets)?;
+        writer.write_u64::<LittleEndian>(self.settings.entry_delay.as_micros() as u64)?;
+        writer.write_f32::<LittleEndian>(self.settings.soft_drop_factor)?;
+
+        writer.write_u32::<LittleEndian>(self.frames.len() as u32)?;
+        for frame in &self.frames {
+            writer.write_u32::<LittleEndian>(frame.dt.as_micros() as u32)?;
+            let action_count = u16::try_from(frame.actions.len())
+                .map_err(|_| Error::new(ErrorKind::InvalidData, format!("{} actions in a single frame", frame.actions.len())))?;
+            writer.write_u16::<LittleEndian>(action_count)?;
+            for action in &frame.actions {
+                writer.write_u8(action.id())?;
+            }
+        }
+
+        writer.flush()
+    }
+
+    pub fn load(filename: &str) -> Result<Self, Error> {
+        let mut reader = BufReader::new(File::open(filename)?);
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidData, format!("{}: {}", filename, message));
+
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a replay file"));
+        }
+        let version = reader.read_u8()?;
+        if version != VERSION {
+            return Err(invalid(&format!("unsupported replay version {}", version)));
+        }
+
+        let randomizer = RandomizerKind::from_id(reader.read_u8()?).ok_or_else(|| invalid("unknown randomizer"))?;
+        let seed = reader.read_u64::<LittleEndian>()?;
+        let mode = Mode::from_id(reader.read_u8()?).ok_or_else(|| invalid("unknown mode"))?;
+        let settings = GameSettings {
+            mode,
+            start_level: reader.read_u32::<LittleEndian>()?,
+            field_size: FieldSize {
+                width: reader.read_u16::<LittleEndian>()? as usize,
+                height: reader.read_u16::<LittleEndian>()? as usize,
+            },
+            sprint_lines: reader.read_u32::<LittleEndian>()?,
+            ultra_time: Duration::from_micros(reader.read_u64::<LittleEndian>()?),
+            dig_rows: reader.read_u32::<LittleEndian>()?,
+            dig_interval: Some(Duration::from_micros(reader.read_u64::<LittleEndian>()?)).filter(|interval| !interval.is_zero()),
+            preview_count: reader.read_u8()? as usize,
+            lock_delay: LockDelay {
+                delay: Duration::from_micros(reader.read_u64::<LittleEndian>()?),
+                max_resets: reader.read_u32::<LittleEndian>()?,
+            },
+            entry_delay: Duration::from_micros(reader.read_u64::<LittleEndian>()?),
+            soft_drop_factor: reader.read_f32::<LittleEndian>()?,
+        };
+        // Replays get shared, so the settings go through the same limits as the command line
+        check_settings(&settings).map_err(|message| invalid(&message))?;
+
+        // The counts aren't trusted to size anything, a damaged file runs out of data instead
+        let frame_count = reader.read_u32::<LittleEndian>()?;
+        let mut frames = Vec::new();
+        for _ in 0..frame_count {
+            let dt = Duration::from_micros(reader.read_u32::<LittleEndian>()? as u64);
+            let action_count = reader.read_u16::<LittleEndian>()?;
+            let mut actions = Vec::new();
+            for _ in 0..action_count {
+                actions.push(GameAction::from_id(reader.read_u8()?).ok_or_else(|| invalid("unknown action"))?);
+            }
+            frames.push(Frame { dt, actions });
+        }
+
+        Ok(Self { randomizer, seed, settings, frames })
+    }
+}
+
+fn check_settings(settings: &GameSettings) -> Result<(), String> {
+    let size = settings.field_size;
+    if !(MIN_FIELD_SIZE.width..=MAX_FIELD_SIZE.width).contains(&size.width) || !(MIN_FIELD_SIZE.height..=MAX_FIELD_SIZE.height).contains(&size.height) {
+        return Err(format!("field size {}x{} is out of range", size.width, size.height));
+    }
+    if !(1..=MAX_START_LEVEL).contains(&settings.start_level) {
+        return Err(format!("start level {} is out of range", settings.start_level));
+    }
+    if !(1..=MAX_PREVIEW_COUNT).contains(&settings.preview_count) {
+        return Err(format!("preview count {} is out of range", settings.preview_count));
+    }
+    if settings.sprint_lines == 0 || settings.ultra_time.is_zero() {
+        return Err("sprint lines and ultra time should be above 0".to_string());
+    }
+    if !(1..=max_dig_rows(size)).contains(&settings.dig_rows) {
+        return Err(format!("{} dig rows don't fit a {}x{} field", settings.dig_rows, size.width, size.height));
+    }
+    if !settings.soft_drop_factor.is_finite() || settings.soft_drop_factor <= 0.0 {
+        return Err(format!("soft drop factor {} should be a number above 0", settings.soft_drop_factor));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+
+    // Byte where the frame count starts, right after the header
+    const FRAME_COUNT_OFFSET: usize = 72;
+
+    // A file in the temp folder, removed when dropped
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            Self(std::env::temp_dir().join(format!("tetrustplus-{}-{}.replay", std::process::id(), name)))
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn replay() -> Replay {
+        let settings = GameSettings { mode: Mode::Dig, start_level: 3, dig_interval: Some(Duration::from_secs(4)), ..GameSettings::default() };
+        let mut replay = Replay::new(RandomizerKind::Nes, 0xDEADBEEF, settings);
+        replay.record(Duration::from_nanos(16_666_667), &[]);
+        replay.record(Duration::from_millis(17), &[GameAction::MoveLeft, GameAction::RotateClockwise]);
+        replay.record(Duration::from_millis(16), &[GameAction::HardDrop]);
+        replay
+    }
+
+    fn saved(name: &str) -> (TempFile, Vec<u8>) {
+        let file = TempFile::new(name);
+        replay().save(file.path()).unwrap();
+        let data = fs::read(file.path()).unwrap();
+        (file, data)
+    }
+
+    #[test]
+    fn save_and_load() {
+        let (file, _) = saved("round-trip");
+        let original = replay();
+        let loaded = Replay::load(file.path()).unwrap();
+
+        assert_eq!(loaded.randomizer, original.randomizer);
+        assert_eq!(loaded.seed, original.seed);
+        assert_eq!(format!("{:?}", loaded.settings), format!("{:?}", original.settings));
+        assert_eq!(loaded.frames().len(), 3);
+        for (loaded, original) in loaded.frames().iter().zip(original.frames()) {
+            assert_eq!((loaded.dt, &loaded.actions), (original.dt, &original.actions));
+        }
+        assert_eq!(loaded.frames()[0].dt, Duration::from_micros(16_666));
+    }
+
+    #[test]
+    fn truncated() {
+        let (file, data) = saved("truncated");
+        for length in [3, FRAME_COUNT_OFFSET - 1, data.len() - 1] {
+            fs::write(file.path(), &data[..length]).unwrap();
+            assert_eq!(Replay::load(file.path()).err().map(|error| error.kind()), Some(ErrorKind::UnexpectedEof));
+        }
+    }
+
+    #[test]
+    fn wrong_version() {
+        let (file, mut data) = saved("version");
+        data[4] = VERSION - 1;
+        fs::write(file.path(), &data).unwrap();
+        assert_eq!(Replay::load(file.path()).err().map(|error| error.kind()), Some(ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn oversized_counts() {
+        let (file, mut data) = saved("counts");
+        assert_eq!(data[FRAME_COUNT_OFFSET..FRAME_COUNT_OFFSET + 4], 3u32.to_le_bytes());
+        data[FRAME_COUNT_OFFSET..FRAME_COUNT_OFFSET + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(file.path(), &data).unwrap();
+        assert!(Replay::load(file.path()).is_err());
+
+        // Action count of the first frame
+        data[FRAME_COUNT_OFFSET + 8..FRAME_COUNT_OFFSET + 10].copy_from_slice(&u16::MAX.to_le_bytes());
+        fs::write(file.path(), &data).unwrap();
+        assert!(Replay::load(file.path()).is_err());
+    }
+
+    #[test]
+    fn settings_out_of_range() {
+        let (file, mut data) = saved("settings");
+        // Preview count, right after the dig interval
+        data[47] = MAX_PREVIEW_COUNT as u8 + 1;
+        fs::write(file.path(), &data).unwrap();
+        let error = Replay::load(file.path()).err().unwrap();
+        assert!(error.to_string().contains("preview count"), "{}", error);
+    }
+}
@@ -0,0 +1,245 @@
+use crate::tetromino::RotationState;
+
+const SOFT_DROP_POINTS: u32 = 1;
+const HARD_DROP_POINTS: u32 = 2;
+const COMBO_POINTS: u32 = 50;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Spin {
+    None,
+    Mini,
+    Full,
+}
+
+/// What happened when a tetromino settled on the board
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Lock {
+    pub lines: u32,
+    pub spin: Spin,
+    pub hard_drop_rows: u32,
+}
+
+/// Outcome of a lock once it went through scoring
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ClearEvent {
+    pub lines: u32,
+    pub spin: Spin,
+    pub back_to_back: bool,
+    pub combo: u32,
+    pub points: u32,
+}
+
+impl ClearEvent {
+    /// Text shown to the player, like "T-SPIN DOUBLE" or "B2B TETRIS"
+    pub fn name(&self) -> Option<String> {
+        let name = clear_name(self.lines, self.spin)?;
+        if self.back_to_back {
+            Some(format!("B2B {}", name))
+        } else {
+            Some(name)
+        }
+    }
+}
+
+pub struct Scoring {
+    score: u32,
+    // How many times each kind of clear happened, by lines and spin
+    clears: Vec<((u32, Spin), u32)>,
+    // Consecutive locks that cleared lines, minus one
+    combo: i32,
+    // Set while the last line clear was a tetris or a T-spin
+    back_to_back: bool,
+}
+
+impl Scoring {
+    pub fn new() -> Self {
+        Self {
+            score: 0,
+            clears: Vec::new(),
+            combo: -1,
+            back_to_back: false,
+        }
+    }
+
+    pub fn score(&self) -> u32 {
+        self.score
+    }
+
+    /// Names and counts of the clears made so far, from the least to the most valuable
+    pub fn clears(&self) -> Vec<(String, u32)> {
+        let mut clears = self.clears.clone();
+        clears.sort_by_key(|&((lines, spin), _)| (clear_points(lines, spin), lines));
+        clears.into_iter()
+            .filter_map(|((lines, spin), count)| Some((clear_name(lines, spin)?, count)))
+            .collect()
+    }
+
+    pub fn soft_drop(&mut self, rows: u32) {
+        self.score += rows * SOFT_DROP_POINTS;
+    }
+
+    pub fn lock(&mut self, lock: Lock, level: u32) -> ClearEvent {
+        let difficult = lock.lines > 0 && (lock.lines >= 4 || lock.spin != Spin::None);
+        let back_to_back = difficult && self.back_to_back;
+        if difficult {
+            self.back_to_back = true;
+        } else if lock.lines > 0 {
+            self.back_to_back = false;
+        }
+
+        let mut points = clear_points(lock.lines, lock.spin) * level;
+        if back_to_back {
+            points += points / 2;
+        }
+
+        if lock.lines > 0 {
+            self.combo += 1;
+            points += COMBO_POINTS * self.combo as u32 * level;
+        } else {
+            self.combo = -1;
+        }
+
+        if lock.lines > 0 || lock.spin != Spin::None {
+            let kind = (lock.lines, lock.spin);
+            match self.clears.iter_mut().find(|(clear, _)| *clear == kind) {
+                Some((_, count)) => *count += 1,
+                None => self.clears.push((kind, 1)),
+            }
+        }
+
+        points += lock.hard_drop_rows * HARD_DROP_POINTS;
+        self.score += points;
+
+        ClearEvent {
+            lines: lock.lines,
+            spin: lock.spin,
+            back_to_back,
+            combo: self.combo.max(0) as u32,
+            points,
+        }
+    }
+}
+
+/// Applies the 3-corner rule to a T tetromino whose last move was a rotation using the given kick.
+/// `is_occupied` is asked about the corners of its 3x3 bounding box.
+pub fn detect_t_spin(rotation: RotationState, kick: (i32, i32), is_occupied: impl Fn(i32, i32) -> bool) -> Spin {
+    let corners = [(0, 0), (2, 0), (0, 2), (2, 2)];
+    if corners.iter().filter(|&&(x, y)| is_occupied(x, y)).count() < 3 {
+        return Spin::None;
+    }
+
+    // The two corners on each side of the point of the T
+    let front = match rotation {
+        RotationState::Spawn => [(0, 0), (2, 0)],
+        RotationState::Right => [(2, 0), (2, 2)],
+        RotationState::Reverse => [(0, 2), (2, 2)],
+        RotationState::Left => [(0, 0), (0, 2)],
+    };
+
+    // A kick moving the T one column and two rows, like the last SRS test, always makes a full T-spin
+    let long_kick = kick.0.abs() == 1 && kick.1.abs() == 2;
+    if front.iter().all(|&(x, y)| is_occupied(x, y)) || long_kick {
+        Spin::Full
+    } else {
+        Spin::Mini
+    }
+}
+
+fn clear_name(lines: u32, spin: Spin) -> Option<String> {
+    let lines = match lines {
+        0 => None,
+        1 => Some("SINGLE"),
+        2 => Some("DOUBLE"),
+        3 => Some("TRIPLE"),
+        _ => Some("TETRIS"),
+    };
+
+    let name = match (spin, lines) {
+        (Spin::None, None) => return None,
+        (Spin::None, Some(lines)) => lines.to_string(),
+        (Spin::Mini, None) => "MINI T-SPIN".to_string(),
+        (Spin::Mini, Some(lines)) => format!("MINI T-SPIN {}", lines),
+        (Spin::Full, None) => "T-SPIN".to_string(),
+        (Spin::Full, Some(lines)) => format!("T-SPIN {}", lines),
+    };
+
+    Some(name)
+}
+
+fn clear_points(lines: u32, spin: Spin) -> u32 {
+    match (spin, lines) {
+        (Spin::None, 0) => 0,
+        (Spin::None, 1) => 100,
+        (Spin::None, 2) => 300,
+        (Spin::None, 3) => 500,
+        (Spin::None, _) => 800,
+        (Spin::Mini, 0) => 100,
+        (Spin::Mini, 1) => 200,
+        (Spin::Mini, _) => 400,
+        (Spin::Full, 0) => 400,
+        (Spin::Full, 1) => 800,
+        (Spin::Full, 2) => 1200,
+        (Spin::Full, _) => 1600,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lock(lines: u32, spin: Spin) -> Lock {
+        Lock { lines, spin, hard_drop_rows: 0 }
+    }
+
+    #[test]
+    fn t_spin_needs_three_corners() {
+        let two_corners = |x, y| (x, y) == (0, 2) || (x, y) == (2, 2);
+        assert_eq!(detect_t_spin(RotationState::Spawn, (0, 0), two_corners), Spin::None);
+    }
+
+    #[test]
+    fn t_spin_mini_without_both_front_corners() {
+        let back_and_one_front = |x, y| y == 2 || (x, y) == (0, 0);
+        assert_eq!(detect_t_spin(RotationState::Spawn, (0, 0), back_and_one_front), Spin::Mini);
+        // The point of a reversed T faces down, so the same corners are both in front of it
+        assert_eq!(detect_t_spin(RotationState::Reverse, (0, 0), back_and_one_front), Spin::Full);
+    }
+
+    #[test]
+    fn long_kick_makes_a_full_t_spin() {
+        let back_and_one_front = |x, y| y == 2 || (x, y) == (0, 0);
+        assert_eq!(detect_t_spin(RotationState::Spawn, (-1, 2), back_and_one_front), Spin::Full);
+    }
+
+    #[test]
+    fn t_spin_points() {
+        let mut scoring = Scoring::new();
+        let event = scoring.lock(lock(2, Spin::Full), 2);
+        assert_eq!(event.points, 2400);
+        assert_eq!(event.name().as_deref(), Some("T-SPIN DOUBLE"));
+
+        assert_eq!(scoring.lock(lock(0, Spin::Mini), 1).points, 100);
+        let event = scoring.lock(lock(1, Spin::Mini), 1);
+        assert_eq!((event.name().as_deref(), event.points), (Some("B2B MINI T-SPIN SINGLE"), 300));
+        assert_eq!(scoring.score(), 2400 + 100 + 300);
+    }
+
+    #[test]
+    fn back_to_back_and_combo() {
+        let mut scoring = Scoring::new();
+        scoring.lock(lock(4, Spin::None), 1);
+
+        // A T-spin keeps the back to back going, and is the second clear in a row
+        let event = scoring.lock(lock(1, Spin::Full), 1);
+        assert!(event.back_to_back);
+        assert_eq!(event.combo, 1);
+        assert_eq!(event.points, 1200 + 50);
+        assert_eq!(event.name().as_deref(), Some("B2B T-SPIN SINGLE"));
+
+        // A single breaks both once a lock clears nothing
+        assert!(!scoring.lock(lock(1, Spin::None), 1).back_to_back);
+        scoring.lock(lock(0, Spin::None), 1);
+        let event = scoring.lock(lock(4, Spin::None), 1);
+        assert_eq!((event.back_to_back, event.combo, event.points), (false, 0, 800));
+    }
+}
@@ -1,124 +1,277 @@
 use std::collections::VecDeque;
 
-use rand::{thread_rng, Rng};
+use crate::{randomizer::Randomizer, renderer::{Block, Renderer}};
 
-use crate::display::Display;
+pub const MAX_PREVIEW_COUNT: usize = 6;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum TetrominoKind {
+    I,
+    O,
+    J,
+    L,
+    S,
+    Z,
+    T,
+}
+
+impl TetrominoKind {
+    pub const ALL: [TetrominoKind; 7] = [
+        TetrominoKind::I,
+        TetrominoKind::O,
+        TetrominoKind::J,
+        TetrominoKind::L,
+        TetrominoKind::S,
+        TetrominoKind::Z,
+        TetrominoKind::T,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TetrominoKind::I => "I",
+            TetrominoKind::O => "O",
+            TetrominoKind::J => "J",
+            TetrominoKind::L => "L",
+            TetrominoKind::S => "S",
+            TetrominoKind::Z => "Z",
+            TetrominoKind::T => "T",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Rotation {
+    Clockwise,
+    CounterClockwise,
+    Half,
+}
+
+/// SRS rotation states, named after the guideline: spawn (0), right (R), reverse (2) and left (L)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RotationState {
+    Spawn,
+    Right,
+    Reverse,
+    Left,
+}
+
+impl RotationState {
+    fn index(self) -> usize {
+        match self {
+            RotationState::Spawn => 0,
+            RotationState::Right => 1,
+            RotationState::Reverse => 2,
+            RotationState::Left => 3,
+        }
+    }
+
+    fn from_index(index: usize) -> Self {
+        match index % 4 {
+            0 => RotationState::Spawn,
+            1 => RotationState::Right,
+            2 => RotationState::Reverse,
+            _ => RotationState::Left,
+        }
+    }
+
+    pub fn rotate(self, rotation: Rotation) -> Self {
+        let quarter_turns = match rotation {
+            Rotation::Clockwise => 1,
+            Rotation::Half => 2,
+            Rotation::CounterClockwise => 3,
+        };
+
+        Self::from_index(self.index() + quarter_turns)
+    }
+}
+
+// Kick offsets as published for SRS, x goes right and y goes up.
+// Rows are 0->R, R->0, R->2, 2->R, 2->L, L->2, L->0, 0->L
+const JLSTZ_KICKS: [[(i32, i32); 5]; 8] = [
+    [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+    [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+    [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+    [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+];
+
+const I_KICKS: [[(i32, i32); 5]; 8] = [
+    [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
+    [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+    [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+    [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+    [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+    [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
+    [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+    [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+];
+
+// 180 degree kicks are not part of SRS, these follow the table used by modern guideline clients.
+// Rows are 0->2, 2->0, R->L, L->R and they apply to every tetromino but O
+const HALF_TURN_KICKS: [[(i32, i32); 6]; 4] = [
+    [(0, 0), (0, 1), (1, 1), (-1, 1), (1, 0), (-1, 0)],
+    [(0, 0), (0, -1), (-1, -1), (1, -1), (-1, 0), (1, 0)],
+    [(0, 0), (1, 0), (1, 2), (1, 1), (0, 2), (0, 1)],
+    [(0, 0), (-1, 0), (-1, 2), (-1, 1), (0, 2), (0, 1)],
+];
+
+const NO_KICKS: [(i32, i32); 1] = [(0, 0)];
 
 #[derive(Debug, Clone)]
 pub struct Representation {
     pub vertices: Vec<(u32, u32)>,
-    pub width: u32,
-    pub height: u32,
-    pub color: u32
 }
 
 #[derive(Clone, Debug)]
 pub struct Tetromino {
-    representations: VecDeque<Representation>,
+    kind: TetrominoKind,
+    // Width and height of the square bounding box the tetromino rotates in
+    size: u32,
+    // One representation per rotation state, indexed by RotationState::index
+    representations: Vec<Representation>,
+    rotation: RotationState,
 }
 
 impl Tetromino {
-    pub fn new(reprensentations: Vec<Representation>) -> Self {
-        Self { representations: VecDeque::from(reprensentations)}
+    /// Builds the four rotation states by rotating the spawn vertices clockwise
+    /// around the center of a size x size bounding box.
+    pub fn new(kind: TetrominoKind, size: u32, spawn_vertices: Vec<(u32, u32)>) -> Self {
+        let mut representations = Vec::with_capacity(4);
+        let mut vertices = spawn_vertices;
+        for _ in 0..4 {
+            let rotated = vertices.iter().map(|&(x, y)| (size - 1 - y, x)).collect();
+            representations.push(Representation { vertices });
+            vertices = rotated;
+        }
+
+        Self { kind, size, representations, rotation: RotationState::Spawn }
+    }
+
+    pub fn kind(&self) -> TetrominoKind {
+        self.kind
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn rotation(&self) -> RotationState {
+        self.rotation
     }
 
     pub fn current_representation(&self) -> Option<&Representation> {
-        self.representations.front()
+        self.representations.get(self.rotation.index())
     }
 
-    pub fn render(&self, x: u32, y: u32, display: &mut Display) {
-        assert!(self.representations.len() > 0, "Tetromino should have at least one representation to render");
+    pub fn render(&self, x: u32, y: u32, renderer: &mut dyn Renderer) {
+        assert!(!self.representations.is_empty(), "Tetromino should have at least one representation to render");
 
-        if let Some(current_representation) = self.representations.front() {
+        if let Some(current_representation) = self.current_representation() {
             for &(vertex_x, vertex_y) in &current_representation.vertices {
-                display.draw_block(current_representation.color, x + vertex_x, y + vertex_y);
+                renderer.draw_block(Block::Piece(self.kind), x + vertex_x, y + vertex_y);
             }
         }
     }
 
-    pub fn render_shadow(&self, x: u32, y: u32, display: &mut Display) {
-        assert!(self.representations.len() > 0, "Tetromino should have at least one representation to render");
+    pub fn reset_rotation(&mut self) {
+        self.rotation = RotationState::Spawn;
+    }
 
-        if let Some(current_representation) = self.representations.front() {
-            for &(vertex_x, vertex_y) in &current_representation.vertices {
-                display.draw_block(0xFF161616, x + vertex_x, y + vertex_y);
-            }
-        }
+    pub fn rotate(&mut self, rotation: Rotation) {
+        assert!(!self.representations.is_empty(), "Block should have at least one representation to rotate");
+
+        self.rotation = self.rotation.rotate(rotation);
     }
 
-    pub fn rotate(&mut self) {
-        assert!(self.representations.len() > 0, "Block should have at least one representation to rotate");
+    /// Offsets to try, in order, when rotating from one state to another.
+    /// They are returned in board coordinates, where y goes down.
+    pub fn kicks(&self, from: RotationState, to: RotationState) -> Vec<(i32, i32)> {
+        if self.kind == TetrominoKind::O {
+            return NO_KICKS.to_vec();
+        }
 
-        if let Some(rep) = self.representations.pop_front() {
-            self.representations.push_back(rep);
+        let half_turn_row = match (from, to) {
+            (RotationState::Spawn, RotationState::Reverse) => Some(0),
+            (RotationState::Reverse, RotationState::Spawn) => Some(1),
+            (RotationState::Right, RotationState::Left) => Some(2),
+            (RotationState::Left, RotationState::Right) => Some(3),
+            _ => None,
+        };
+
+        if let Some(row) = half_turn_row {
+            return HALF_TURN_KICKS[row].iter().map(|&(x, y)| (x, -y)).collect();
         }
+
+        let table = if self.kind == TetrominoKind::I { &I_KICKS } else { &JLSTZ_KICKS };
+
+        let row = match (from, to) {
+            (RotationState::Spawn, RotationState::Right) => 0,
+            (RotationState::Right, RotationState::Spawn) => 1,
+            (RotationState::Right, RotationState::Reverse) => 2,
+            (RotationState::Reverse, RotationState::Right) => 3,
+            (RotationState::Reverse, RotationState::Left) => 4,
+            (RotationState::Left, RotationState::Reverse) => 5,
+            (RotationState::Left, RotationState::Spawn) => 6,
+            (RotationState::Spawn, RotationState::Left) => 7,
+            _ => return NO_KICKS.to_vec(),
+        };
+
+        table[row].iter().map(|&(x, y)| (x, -y)).collect()
     }
 }
 
 pub struct TetrominoGenertor {
+    // Ordered like TetrominoKind::ALL
     cache: Vec<Tetromino>,
+    randomizer: Box<dyn Randomizer>,
+    // Upcoming tetrominos, the front one is handed out next
+    queue: VecDeque<Tetromino>,
 }
 
 impl TetrominoGenertor {
-    pub fn new() -> Self {
-        let mut tetrominos = Vec::with_capacity(7);
+    pub fn new(randomizer: Box<dyn Randomizer>, preview_count: usize) -> Self {
+        assert!((1..=MAX_PREVIEW_COUNT).contains(&preview_count), "Preview count should be between 1 and {}", MAX_PREVIEW_COUNT);
 
-        // I Block
-        let representations = vec![
-            Representation { vertices: vec![(0, 0), (0, 1), (0, 2), (0, 3)], width: 1, height: 4, color: 0xFF00F0F0 },
-            Representation { vertices: vec![(0, 0), (1, 0), (2, 0), (3, 0)], width: 4, height: 1, color: 0xFF00F0F0 },
-        ];
-        tetrominos.push(Tetromino::new(representations));
-        // O Tetromino
-        let representations = vec![
-            Representation { vertices: vec![(0, 0), (1, 0), (0, 1), (1, 1)], width: 2, height: 2, color: 0xF0F000 },
-        ];
-        tetrominos.push(Tetromino::new(representations));
-        // J Tetromino
-        let representations = vec![
-            Representation { vertices: vec![(1, 0), (1, 1), (0, 2), (1, 2)], width: 2, height: 3, color: 0xFF0000F0 },
-            Representation { vertices: vec![(0, 0), (0, 1), (1, 1), (2, 1)], width: 3, height: 2, color: 0xFF0000F0 },
-            Representation { vertices: vec![(0, 0), (1, 0), (0, 1), (0, 2)], width: 2, height: 3, color: 0xFF0000F0 },
-            Representation { vertices: vec![(0, 0), (1, 0), (2, 0), (2, 1)], width: 3, height: 2, color: 0xFF0000F0 },
-        ];
-        tetrominos.push(Tetromino::new(representations));
-        // L Tetromino
-        let representations = vec![
-            Representation { vertices: vec![(0, 0), (0, 1), (0, 2), (1, 2)], width: 2, height: 3, color: 0xFFF0A000 },
-            Representation { vertices: vec![(0, 0), (1, 0), (2, 0), (0, 1)], width: 3, height: 2, color: 0xFFF0A000 },
-            Representation { vertices: vec![(0, 0), (1, 0), (1, 1), (1, 2)], width: 2, height: 3, color: 0xFFF0A000 },
-            Representation { vertices: vec![(0, 1), (1, 1), (2, 1), (2, 0)], width: 3, height: 2, color: 0xFFF0A000 },
-        ];
-        tetrominos.push(Tetromino::new(representations));
-        // S Tetromino
-        let representations = vec![
-            Representation { vertices: vec![(1, 0), (2, 0), (0, 1), (1, 1)], width: 3, height: 2, color: 0xFF00F000 },
-            Representation { vertices: vec![(0, 0), (0, 1), (1, 1), (1, 2)], width: 2, height: 3, color: 0xFF00F000 },
-        ];
-        tetrominos.push(Tetromino::new(representations));
-        // Z Tetromino
-        let representations = vec![
-            Representation { vertices: vec![(0, 0), (1, 0), (1, 1), (2, 1)], width: 3, height: 2, color: 0xFFF00000 },
-            Representation { vertices: vec![(1, 0), (1, 1), (0, 1), (0, 2)], width: 2, height: 3, color: 0xFFF00000 },
-        ];
-        tetrominos.push(Tetromino::new(representations));
-        // T Tetromino
-        let representations = vec![
-            Representation { vertices: vec![(0, 0), (1, 0), (2, 0), (1, 1)], width: 3, height: 2, color: 0xFFA000F0 },
-            Representation { vertices: vec![(1, 0), (1, 1), (1, 2), (0, 1)], width: 2, height: 3, color: 0xFFA000F0 },
-            Representation { vertices: vec![(0, 1), (1, 1), (2, 1), (1, 0)], width: 3, height: 2, color: 0xFFA000F0 },
-            Representation { vertices: vec![(0, 0), (0, 1), (0, 2), (1, 1)], width: 2, height: 3, color: 0xFFA000F0 },
+        let tetrominos = vec![
+            Tetromino::new(TetrominoKind::I, 4, vec![(0, 1), (1, 1), (2, 1), (3, 1)]),
+            Tetromino::new(TetrominoKind::O, 2, vec![(0, 0), (1, 0), (0, 1), (1, 1)]),
+            Tetromino::new(TetrominoKind::J, 3, vec![(0, 0), (0, 1), (1, 1), (2, 1)]),
+            Tetromino::new(TetrominoKind::L, 3, vec![(2, 0), (0, 1), (1, 1), (2, 1)]),
+            Tetromino::new(TetrominoKind::S, 3, vec![(1, 0), (2, 0), (0, 1), (1, 1)]),
+            Tetromino::new(TetrominoKind::Z, 3, vec![(0, 0), (1, 0), (1, 1), (2, 1)]),
+            Tetromino::new(TetrominoKind::T, 3, vec![(1, 0), (0, 1), (1, 1), (2, 1)]),
         ];
-        tetrominos.push(Tetromino::new(representations));
 
-        Self {
+        let mut generator = Self {
             cache: tetrominos,
+            randomizer,
+            queue: VecDeque::with_capacity(preview_count),
+        };
+
+        for _ in 0..preview_count {
+            let tetromino = generator.get_random_tetromino();
+            generator.queue.push_back(tetromino);
         }
+
+        generator
+    }
+
+    /// Hands out the front of the queue and refills it at the back
+    pub fn next_tetromino(&mut self) -> Tetromino {
+        let tetromino = self.get_random_tetromino();
+        self.queue.push_back(tetromino);
+        self.queue.pop_front().unwrap_or_else(|| self.get_random_tetromino())
+    }
+
+    pub fn queue(&self) -> &VecDeque<Tetromino> {
+        &self.queue
     }
 
-    pub fn get_random_tetromino(&self) -> Tetromino {
-        let mut rng = thread_rng();
-        let index = rng.gen_range(0..self.cache.len());
-        self.cache[index].clone()
+    fn get_random_tetromino(&mut self) -> Tetromino {
+        let kind = self.randomizer.next_kind();
+        self.cache[kind as usize].clone()
     }
 }
@@ -0,0 +1,234 @@
+use std::{collections::HashMap, fs, io::{Error, ErrorKind}, path::{Path, PathBuf}, time::SystemTime};
+
+use crate::{bitmap::Bitmap, board::LOCK_DIM_STEPS, config::{Config, Value}, image::{asset_path, load_image}, renderer::Block, tetromino::TetrominoKind};
+
+pub const DEFAULT_THEME: &str = "default";
+const THEMES_DIR: &str = "./themes";
+const THEME_FILE: &str = "theme.toml";
+
+pub const BLOCK_SIZE: usize = 16;
+pub const FONT_WIDTH: u32 = 64;
+const FONT_HEIGHT: u32 = 192;
+
+// Brightness left to a tetromino about to lock
+const LOCKED_BRIGHTNESS: f32 = 0.4;
+// Opacity of ghosts drawn like their piece
+const GHOST_PIECE_ALPHA: u32 = 0x50;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GhostStyle {
+    // The block tinted with the same color whatever the piece
+    Color(u32),
+    // A see-through copy of the piece
+    Piece,
+}
+
+/// How blocks, text and the background are drawn, read from `themes/<name>/theme.toml`:
+///
+/// ```toml
+/// [theme]
+/// block = "block.png"
+/// font = "glyphs.png"
+/// background = "#1D1D1D"
+/// ghost = "piece"
+///
+/// [colors]
+/// T = "#A000F0"
+///
+/// [textures]
+/// border = "border.png"
+/// ```
+///
+/// The default theme has no file, it uses `./block` and `./glyphs` and the colors below.
+pub struct Theme {
+    pub name: String,
+    pub background: u32,
+    // Tinted with the color of each block, unless that block has a texture of its own
+    block: Bitmap,
+    pub font: Bitmap,
+    field: u32,
+    border: u32,
+    garbage: u32,
+    ghost: GhostStyle,
+    // Ordered like TetrominoKind::ALL
+    piece_colors: [u32; 7],
+    // Drawn as they are, for pieces, the border and garbage
+    textures: HashMap<Block, Bitmap>,
+    // Files the theme was read from and when they were last changed
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
+impl Theme {
+    /// Loads a theme by the name of its folder in `./themes`
+    pub fn load(name: &str) -> Result<Self, Error> {
+        let (dir, config) = if name == DEFAULT_THEME {
+            (PathBuf::from("."), Config::default())
+        } else {
+            let dir = Path::new(THEMES_DIR).join(name);
+            let file = dir.join(THEME_FILE);
+            if !file.exists() {
+                return Err(Error::new(ErrorKind::NotFound, format!("Unknown theme {}, {} is missing", name, file.display())));
+            }
+            let config = Config::load(&file.to_string_lossy())?;
+            (dir, config)
+        };
+        let empty = HashMap::new();
+        let section = |name: &str| config.section(name).unwrap_or(&empty);
+        let invalid = |message: String| Error::new(ErrorKind::InvalidData, format!("Theme {}: {}", name, message));
+
+        let mut files = Vec::new();
+        if name != DEFAULT_THEME {
+            files.push((dir.join(THEME_FILE), None));
+        }
+        let mut load = |key: &str, value: Option<&Value>, default: &str, size: (u32, u32)| -> Result<Bitmap, Error> {
+            let path = match value {
+                Some(value) => dir.join(value.as_str().ok_or_else(|| invalid(format!("{} should be a file name", key)))?),
+                None => asset_path(Path::new("."), default),
+            };
+            let bitmap = load_image(&path).map_err(|error| invalid(format!("{}: {}", path.display(), error)))?;
+            files.push((path.clone(), None));
+            if (bitmap.width, bitmap.height) != size {
+                return Err(invalid(format!("{} should be {}x{}", path.display(), size.0, size.1)));
+            }
+            Ok(bitmap)
+        };
+
+        let block_size = (BLOCK_SIZE as u32, BLOCK_SIZE as u32);
+        let theme = section("theme");
+        let block = load("block", theme.get("block"), "block", block_size)?;
+        let font = load("font", theme.get("font"), "glyphs", (FONT_WIDTH, FONT_HEIGHT))?;
+
+        let mut textures = HashMap::new();
+        for (key, value) in section("textures") {
+            let block = block_from_name(key).ok_or_else(|| invalid(format!("unknown texture {}", key)))?;
+            textures.insert(block, load(key, Some(value), "", block_size)?);
+        }
+
+        let color = |section: &HashMap<String, Value>, key: &str, default: u32| match section.get(key) {
+            Some(value) => value.as_str().and_then(parse_color)
+                .ok_or_else(|| invalid(format!("{} should be a color like \"#RRGGBB\" or \"#AARRGGBB\"", key))),
+            None => Ok(default),
+        };
+        let ghost = match theme.get("ghost") {
+            Some(Value::String(style)) if style == "piece" => GhostStyle::Piece,
+            _ => GhostStyle::Color(color(theme, "ghost", 0xFF161616)?),
+        };
+        let mut piece_colors = [0xFF00F0F0, 0xFFF0F000, 0xFF0000F0, 0xFFF0A000, 0xFF00F000, 0xFFF00000, 0xFFA000F0];
+        for (kind, piece_color) in TetrominoKind::ALL.iter().zip(&mut piece_colors) {
+            *piece_color = color(section("colors"), kind.name(), *piece_color)?;
+        }
+        if let Some(key) = section("colors").keys().find(|key| !TetrominoKind::ALL.iter().any(|kind| kind.name() == *key)) {
+            return Err(invalid(format!("unknown piece {} in [colors]", key)));
+        }
+
+        let mut theme = Self {
+            name: name.to_string(),
+            background: color(theme, "background", 0xFF1D1D1D)?,
+            block,
+            font,
+            field: color(theme, "field", 0xFF000000)?,
+            border: color(theme, "border", 0xFF999999)?,
+            garbage: color(theme, "garbage", 0xFF5A5A5A)?,
+            ghost,
+            piece_colors,
+            textures,
+            files,
+        };
+        theme.mark_seen();
+        Ok(theme)
+    }
+
+    /// Whether a file of the theme changed since it was loaded or last marked as seen
+    pub fn has_changed(&self) -> bool {
+        self.files.iter().any(|(path, modified)| modified_time(path) != *modified)
+    }
+
+    /// Takes the current state of the files as the loaded one, so a theme that failed to reload
+    /// is only tried again after its next change
+    pub fn mark_seen(&mut self) {
+        for (path, modified) in &mut self.files {
+            *modified = modified_time(path);
+        }
+    }
+
+    /// Pixels of a block, BLOCK_SIZE wide and high
+    pub fn block_pixels(&self, block: Block) -> Vec<u32> {
+        match block {
+            Block::Locking(kind, step) => {
+                let brightness = 1.0 - step as f32 / LOCK_DIM_STEPS as f32 * (1.0 - LOCKED_BRIGHTNESS);
+                self.block_pixels(Block::Piece(kind)).into_iter().map(|pixel| scale(pixel, brightness)).collect()
+            }
+            Block::Ghost(kind) => match self.ghost {
+                GhostStyle::Color(color) => tint(&self.block.pixels, color),
+                GhostStyle::Piece => self.block_pixels(Block::Piece(kind)).into_iter()
+                    .map(|pixel| ((pixel >> 24) * GHOST_PIECE_ALPHA / 0xFF) << 24 | (pixel & 0xFFFFFF))
+                    .collect(),
+            },
+            _ if self.textures.contains_key(&block) => self.textures[&block].pixels.clone(),
+            Block::Piece(kind) => tint(&self.block.pixels, self.piece_colors[TetrominoKind::ALL.iter().position(|&k| k == kind).unwrap_or(0)]),
+            Block::Empty => tint(&self.block.pixels, self.field),
+            Block::Garbage => tint(&self.block.pixels, self.garbage),
+            Block::Border => tint(&self.block.pixels, self.border),
+        }
+    }
+}
+
+/// Names of the themes to pick from, the default one first and then the folders of `./themes` in order
+pub fn theme_names() -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(THEMES_DIR).into_iter().flatten().flatten()
+        .filter(|entry| entry.path().join(THEME_FILE).exists())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| name != DEFAULT_THEME)
+        .collect();
+    names.sort();
+    names.insert(0, DEFAULT_THEME.to_string());
+    names
+}
+
+fn block_from_name(name: &str) -> Option<Block> {
+    match name {
+        "border" => Some(Block::Border),
+        "garbage" => Some(Block::Garbage),
+        "field" => Some(Block::Empty),
+        name => TetrominoKind::ALL.iter().find(|kind| kind.name() == name).map(|&kind| Block::Piece(kind)),
+    }
+}
+
+// "#RRGGBB" is opaque, "#AARRGGBB" has an alpha
+fn parse_color(text: &str) -> Option<u32> {
+    let hex = text.strip_prefix('#')?;
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    match hex.len() {
+        6 => Some(0xFF000000 | value),
+        8 => Some(value),
+        _ => None,
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+// Colors a grayscale template, its brightness scaling the color. Both the template and the color can be translucent
+fn tint(template: &[u32], color: u32) -> Vec<u32> {
+    template.iter().map(|&pixel| {
+        let lum_r = ((pixel >> 16) & 0xFF) as f32 / 255.0;
+        let lum_g = ((pixel >> 8) & 0xFF) as f32 / 255.0;
+        let lum_b = (pixel & 0xFF) as f32 / 255.0;
+
+        let new_r = (color >> 16) & 0xFF;
+        let new_g = (color >> 8) & 0xFF;
+        let new_b = color & 0xFF;
+
+        let alpha = (pixel >> 24) * (color >> 24) / 0xFF;
+        alpha << 24 | ((new_r as f32 * lum_r) as u32) << 16 | ((new_g as f32 * lum_g) as u32) << 8 | (new_b as f32 * lum_b) as u32
+    }).collect()
+}
+
+fn scale(pixel: u32, brightness: f32) -> u32 {
+    let r = (((pixel >> 16) & 0xFF) as f32 * brightness) as u32;
+    let g = (((pixel >> 8) & 0xFF) as f32 * brightness) as u32;
+    let b = ((pixel & 0xFF) as f32 * brightness) as u32;
+
+    (pixel & 0xFF000000) | r << 16 | g << 8 | b
+}
@@ -0,0 +1,73 @@
+use crate::{game::GameSettings, menu::{Menu, MenuEvent, MenuInput}, mode::Mode, randomizer::RandomizerKind};
+
+pub const MAX_START_LEVEL: u32 = 15;
+
+const LEVEL_ITEM: usize = Mode::ALL.len();
+const RANDOMIZER_ITEM: usize = Mode::ALL.len() + 1;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ModeSelectChoice {
+    Start(Mode),
+    Back,
+}
+
+/// Lists the modes to start a game in, followed by the starting level and the randomizer
+/// which are changed with left and right
+pub struct ModeSelect {
+    menu: Menu,
+    // Settings the games start with, the level is the one picked here
+    settings: GameSettings,
+    randomizer: RandomizerKind,
+}
+
+impl ModeSelect {
+    pub fn new(settings: GameSettings, randomizer: RandomizerKind) -> Self {
+        let mut items: Vec<String> = Mode::ALL.iter().map(|mode| mode.name().to_string()).collect();
+        items.extend([String::new(), String::new()]);
+
+        let mut select = Self { menu: Menu::new("SELECT MODE", items), settings, randomizer };
+        select.update_items();
+        select
+    }
+
+    pub fn menu(&self) -> &Menu {
+        &self.menu
+    }
+
+    pub fn settings(&self) -> &GameSettings {
+        &self.settings
+    }
+
+    pub fn randomizer(&self) -> RandomizerKind {
+        self.randomizer
+    }
+
+    /// The mode under the cursor, None while it is on the level or the randomizer
+    pub fn highlighted_mode(&self) -> Option<Mode> {
+        Mode::ALL.get(self.menu.selected()).copied()
+    }
+
+    pub fn handle(&mut self, input: MenuInput) -> Option<ModeSelectChoice> {
+        match self.menu.handle(input)? {
+            MenuEvent::Selected(index) if index < Mode::ALL.len() => return Some(ModeSelectChoice::Start(Mode::ALL[index])),
+            MenuEvent::Changed(LEVEL_ITEM, direction) => {
+                self.settings.start_level = self.settings.start_level.saturating_add_signed(direction).clamp(1, MAX_START_LEVEL);
+            }
+            MenuEvent::Changed(RANDOMIZER_ITEM, direction) => {
+                let count = RandomizerKind::ALL.len() as i32;
+                let index = RandomizerKind::ALL.iter().position(|&kind| kind == self.randomizer).unwrap_or(0) as i32;
+                self.randomizer = RandomizerKind::ALL[(index + direction).rem_euclid(count) as usize];
+            }
+            MenuEvent::Back => return Some(ModeSelectChoice::Back),
+            _ => {},
+        }
+
+        self.update_items();
+        None
+    }
+
+    fn update_items(&mut self) {
+        self.menu.set_item(LEVEL_ITEM, format!("LEVEL {}", self.settings.start_level));
+        self.menu.set_item(RANDOMIZER_ITEM, format!("RANDOMIZER {}", self.randomizer.name().to_uppercase()));
+    }
+}
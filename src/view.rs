@@ -0,0 +1,173 @@
+use crate::{board::Board, game::Game, menu::Menu, mode::{format_time, Mode}, records::Records, renderer::{BlendMode, Renderer}, scoring::ClearEvent, title::ModeSelect};
+
+const BOARD_X: u32 = 1;
+const BOARD_Y: u32 = 5;
+// The sidebar never starts left of this column, even next to narrow boards
+const MIN_QUEUE_X: u32 = 30;
+// Width of the hold and next column, the stats column comes right after it
+const QUEUE_COLUMN_WIDTH: u32 = 8;
+// Multiplied over the field once the game is over, halving its brightness
+const DIM_COLOR: u32 = 0xFF808080;
+
+/// Where the board and the sidebar columns go, worked out from the size of the board
+struct Layout {
+    board_width: u32,
+    board_height: u32,
+    // Column of the held and next tetrominos
+    queue_x: u32,
+    // Column of the score and the other stats
+    stats_x: u32,
+}
+
+impl Layout {
+    fn new(board: &Board) -> Self {
+        let (board_width, board_height) = board.render_size();
+        let queue_x = (BOARD_X + board_width + 3).max(MIN_QUEUE_X);
+        Self { board_width, board_height, queue_x, stats_x: queue_x + QUEUE_COLUMN_WIDTH }
+    }
+
+    // Draws text centered on the board, the middle row being y = 0
+    fn draw_on_board(&self, renderer: &mut dyn Renderer, y: i32, text: &str) {
+        let x = BOARD_X + self.board_width.saturating_sub(text.len() as u32) / 2;
+        let y = (BOARD_Y + self.board_height / 2) as i32 + y;
+        renderer.draw_text(x, y as u32, text.to_string());
+    }
+
+    // Darkens the field so the end of game text stands out over it
+    fn dim_field(&self, renderer: &mut dyn Renderer) {
+        let (width, height) = (self.board_width - 2, self.board_height - 2);
+        renderer.fill_rect(DIM_COLOR, BOARD_X + 1, BOARD_Y + 1, width, height, BlendMode::Multiply);
+    }
+}
+
+/// Draws the board and the sidebar around it
+pub fn render_game(game: &Game, renderer: &mut dyn Renderer) {
+    let layout = Layout::new(game.board());
+    renderer.draw_text(28, 1, "tetris!".to_string());
+
+    // The field and the upcoming tetrominos stay hidden while paused so pausing can't be used to plan ahead
+    if game.is_paused() {
+        game.board().render_border(renderer, BOARD_X, BOARD_Y);
+    } else {
+        game.board().render(renderer, BOARD_X, BOARD_Y);
+        draw_held_tetromino(game, &layout, renderer);
+        draw_next_tetrominos(game, &layout, renderer);
+    }
+    draw_score(game, &layout, renderer);
+    if game.is_over() {
+        draw_statistics(game, &layout, renderer);
+    } else if let Some(event) = game.clear_event() {
+        draw_clear_event(event, &layout, renderer);
+    }
+
+    if game.is_over() {
+        layout.dim_field(renderer);
+    }
+    if game.is_finished() {
+        layout.draw_on_board(renderer, -2, "FINISHED");
+        layout.draw_on_board(renderer, 0, "PRESS ENTER");
+    } else if game.is_over() {
+        layout.draw_on_board(renderer, -2, "GAME OVER");
+        layout.draw_on_board(renderer, 0, "PRESS ENTER");
+    }
+}
+
+pub fn render_title(renderer: &mut dyn Renderer) {
+    renderer.draw_text(28, 18, "tetris!".to_string());
+    renderer.draw_text(26, 22, "PRESS ENTER".to_string());
+}
+
+/// Draws the mode select with the description and the personal best of the mode under the cursor
+pub fn render_mode_select(select: &ModeSelect, records: &Records, renderer: &mut dyn Renderer) {
+    renderer.draw_text(28, 1, "tetris!".to_string());
+    select.menu().render(renderer, 4, 6);
+
+    if let Some(mode) = select.highlighted_mode() {
+        renderer.draw_text(4, 18, mode.description(select.settings()));
+        let best = match records.best(mode, select.settings()) {
+            Some(best) => mode.format_result(best),
+            None => "-".to_string(),
+        };
+        renderer.draw_text(4, 20, format!("BEST {}", best));
+    }
+}
+
+/// Draws a menu inside the board
+pub fn render_menu(menu: &Menu, renderer: &mut dyn Renderer) {
+    menu.render(renderer, BOARD_X + 1, BOARD_Y + 3);
+}
+
+fn draw_held_tetromino(game: &Game, layout: &Layout, renderer: &mut dyn Renderer) {
+    renderer.draw_text(layout.queue_x, 3, "HOLD".to_string());
+    if let Some(tetromino) = game.held_tetromino() {
+        tetromino.render(layout.queue_x, 4, renderer);
+    }
+}
+
+fn draw_next_tetrominos(game: &Game, layout: &Layout, renderer: &mut dyn Renderer) {
+    renderer.draw_text(layout.queue_x, 8, "NEXT".to_string());
+    // The first tetromino stands apart from the rest of the queue
+    let mut y = 9;
+    for (i, tetromino) in game.next_tetrominos().iter().enumerate() {
+        tetromino.render(layout.queue_x, y, renderer);
+        y += if i == 0 { 4 } else { 3 };
+    }
+}
+
+fn draw_score(game: &Game, layout: &Layout, renderer: &mut dyn Renderer) {
+    let x = layout.stats_x;
+    renderer.draw_text(x, 3, "SCORE".to_string());
+    renderer.draw_text(x, 4, game.scoring().score().to_string());
+    renderer.draw_text(x, 6, "LEVEL".to_string());
+    renderer.draw_text(x, 7, game.level().level().to_string());
+    renderer.draw_text(x, 9, "LINES".to_string());
+    if game.settings().mode == Mode::Sprint {
+        renderer.draw_text(x, 10, format!("{}/{}", game.level().lines(), game.settings().sprint_lines));
+    } else {
+        renderer.draw_text(x, 10, game.level().lines().to_string());
+    }
+    if game.settings().mode == Mode::Dig {
+        renderer.draw_text(x + 9, 9, "GARBAGE".to_string());
+        renderer.draw_text(x + 9, 10, game.board().garbage_rows().to_string());
+    }
+    // Timed modes count down to the end of the game
+    match game.settings().mode.time_limit(game.settings()) {
+        Some(limit) => {
+            renderer.draw_text(x, 12, "TIME LEFT".to_string());
+            renderer.draw_text(x, 13, format_time(limit.saturating_sub(game.time())));
+        }
+        None => {
+            renderer.draw_text(x, 12, "TIME".to_string());
+            renderer.draw_text(x, 13, format_time(game.time()));
+        }
+    }
+    renderer.draw_text(x, 15, "PPS".to_string());
+    let seconds = game.time().as_secs_f32();
+    let pps = if seconds > 0.0 { game.pieces() as f32 / seconds } else { 0.0 };
+    renderer.draw_text(x, 16, format!("{:.2}", pps));
+}
+
+fn draw_clear_event(event: &ClearEvent, layout: &Layout, renderer: &mut dyn Renderer) {
+    let x = layout.stats_x;
+    let mut y = 18;
+    if let Some(name) = event.name() {
+        renderer.draw_text(x, y, name);
+        y += 1;
+    }
+
+    if event.combo > 0 {
+        renderer.draw_text(x, y, format!("COMBO {}", event.combo));
+        y += 1;
+    }
+
+    renderer.draw_text(x, y, format!("+{}", event.points));
+}
+
+/// End of game statistics, the pieces placed and how many of each clear were made
+fn draw_statistics(game: &Game, layout: &Layout, renderer: &mut dyn Renderer) {
+    let x = layout.stats_x;
+    renderer.draw_text(x, 18, format!("PIECES {}", game.pieces()));
+    for (y, (name, count)) in (20..).zip(game.scoring().clears()) {
+        renderer.draw_text(x, y, format!("{} {}", name, count));
+    }
+}